@@ -0,0 +1,811 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    cell::UnsafeCell,
+    collections::HashMap,
+};
+
+use super::{
+    change_detection::ComponentTicks,
+    components::{Component, ComponentsData},
+    engine::EntityID,
+    paged_vec::PagedVec,
+};
+
+pub type ArchetypeId = usize;
+
+/// Entities with no table components live here
+pub const EMPTY_ARCHETYPE: ArchetypeId = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntityLocation {
+    pub archetype: ArchetypeId,
+    pub row: usize,
+}
+
+/// Type erased `TableData<T>` holding one component type of an archetype
+pub trait TableColumn: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+    /// Empty column of the same component type
+    fn empty(&self) -> Box<dyn TableColumn>;
+    fn swap_remove(&mut self, row: usize);
+    fn reserve(&mut self, additional: usize);
+    /// Swap removes the row and pushes it onto `target`, which must hold the same type
+    fn move_row(&mut self, row: usize, target: &mut dyn TableColumn);
+    /// Swap removes the row and inserts it into the entity of another world
+    fn move_to(&mut self, row: usize, target: &mut ComponentsData, entity: EntityID);
+    /// Pushes every row of `other`, which must hold the same type
+    fn append(&mut self, other: Box<dyn TableColumn>);
+    fn component_name(&self) -> &'static str;
+    /// # Safety
+    /// The caller holds the lock of the component type or has exclusive access to the tables
+    unsafe fn ticks_of(&self, row: usize) -> ComponentTicks;
+    /// Deep copy through `Component::snapshot`, None if the component type doesn't opt in
+    /// # Safety
+    /// Same as `ticks_of`
+    unsafe fn snapshot(&self) -> Option<Box<dyn TableColumn>>;
+}
+
+/// Components and their change ticks, row by row
+pub struct TableData<T> {
+    data: Vec<UnsafeCell<T>>,
+    ticks: Vec<UnsafeCell<ComponentTicks>>,
+}
+
+//Shared access to the rows goes through the lock of `T` in `ComponentsData`
+unsafe impl<T: Component> Sync for TableData<T> {}
+
+impl<T: Component> TableData<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            data: vec![],
+            ticks: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, component: T, ticks: ComponentTicks) {
+        self.data.push(UnsafeCell::new(component));
+        self.ticks.push(UnsafeCell::new(ticks));
+    }
+
+    pub(crate) fn take(&mut self, row: usize) -> T {
+        self.ticks.swap_remove(row);
+        self.data.swap_remove(row).into_inner()
+    }
+
+    pub(crate) fn get_mut(&mut self, row: usize) -> (&mut T, &mut ComponentTicks) {
+        (self.data[row].get_mut(), self.ticks[row].get_mut())
+    }
+
+    /// Start of the rows. Cells can be written through once the write lock of `T` is held
+    pub(crate) fn as_ptr(&self) -> *const UnsafeCell<T> {
+        self.data.as_ptr()
+    }
+
+    /// Start of the change ticks, one per row
+    pub(crate) fn ticks_ptr(&self) -> *const UnsafeCell<ComponentTicks> {
+        self.ticks.as_ptr()
+    }
+}
+
+impl<T: Component> TableColumn for TableData<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+
+    fn empty(&self) -> Box<dyn TableColumn> {
+        Box::new(TableData::<T>::new())
+    }
+
+    fn swap_remove(&mut self, row: usize) {
+        self.data.swap_remove(row);
+        self.ticks.swap_remove(row);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.data.reserve(additional);
+        self.ticks.reserve(additional);
+    }
+
+    fn move_row(&mut self, row: usize, target: &mut dyn TableColumn) {
+        let target = target.as_any_mut().downcast_mut::<TableData<T>>().unwrap();
+        target.data.push(self.data.swap_remove(row));
+        target.ticks.push(self.ticks.swap_remove(row));
+    }
+
+    fn move_to(&mut self, row: usize, target: &mut ComponentsData, entity: EntityID) {
+        let component = self.take(row);
+        target.insert(entity, component);
+    }
+
+    fn append(&mut self, other: Box<dyn TableColumn>) {
+        let other = other.into_any().downcast::<TableData<T>>().unwrap();
+        self.data.extend(other.data);
+        self.ticks.extend(other.ticks);
+    }
+
+    fn component_name(&self) -> &'static str {
+        type_name::<T>()
+    }
+
+    unsafe fn ticks_of(&self, row: usize) -> ComponentTicks {
+        *self.ticks[row].get()
+    }
+
+    unsafe fn snapshot(&self) -> Option<Box<dyn TableColumn>> {
+        let mut column = TableData::<T>::new();
+        column.reserve(self.len());
+
+        for row in 0..self.len() {
+            column.push((*self.data[row].get()).snapshot()?, self.ticks_of(row));
+        }
+
+        Some(Box::new(column))
+    }
+}
+
+/// Table of all the entities sharing the exact same set of table components.
+/// Row `n` of every column belongs to `entities[n]`
+pub struct Archetype {
+    types: Vec<TypeId>,
+    columns: HashMap<TypeId, Box<dyn TableColumn>>,
+    entities: Vec<EntityID>,
+}
+
+impl Archetype {
+    fn new(types: Vec<TypeId>, columns: HashMap<TypeId, Box<dyn TableColumn>>) -> Self {
+        Self {
+            types,
+            columns,
+            entities: vec![],
+        }
+    }
+
+    pub fn entities(&self) -> &[EntityID] {
+        &self.entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn contains<T: Component>(&self) -> bool {
+        self.columns.contains_key(&TypeId::of::<T>())
+    }
+
+    pub(crate) fn column<T: Component>(&self) -> Option<&TableData<T>> {
+        self.columns
+            .get(&TypeId::of::<T>())
+            .and_then(|column| column.as_any().downcast_ref::<TableData<T>>())
+    }
+
+    pub(crate) fn column_mut<T: Component>(&mut self) -> Option<&mut TableData<T>> {
+        self.columns
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|column| column.as_any_mut().downcast_mut::<TableData<T>>())
+    }
+}
+
+/// Table storage. Entities are packed per archetype and move between
+/// archetypes when a table component is added or removed.
+/// Rows move by design so, unlike sparse columns, tables are not paged
+pub struct Archetypes {
+    archetypes: Vec<Archetype>,
+    index: HashMap<Vec<TypeId>, ArchetypeId>,
+    locations: PagedVec<Option<EntityLocation>>,
+}
+
+impl Archetypes {
+    pub fn new() -> Self {
+        let mut index = HashMap::new();
+        index.insert(vec![], EMPTY_ARCHETYPE);
+
+        Self {
+            archetypes: vec![Archetype::new(vec![], HashMap::new())],
+            index,
+            locations: PagedVec::new(),
+        }
+    }
+
+    pub fn location(&self, id: usize) -> Option<EntityLocation> {
+        self.locations.get(id).copied().flatten()
+    }
+
+    pub(crate) fn archetypes(&self) -> &[Archetype] {
+        &self.archetypes
+    }
+
+    pub fn has_column<T: Component>(&self) -> bool {
+        self.archetypes
+            .iter()
+            .any(|archetype| archetype.contains::<T>())
+    }
+
+    /// Places the entity in the empty archetype
+    pub fn spawn(&mut self, entity: EntityID) {
+        self.spawn_in(entity, EMPTY_ARCHETYPE);
+    }
+
+    /// Pushes the entity onto `archetype`. The caller pushes its row in every column right after
+    pub(crate) fn spawn_in(&mut self, entity: EntityID, archetype: ArchetypeId) -> usize {
+        if entity.id >= self.locations.len() {
+            self.locations.resize_with(entity.id + 1, || None);
+        }
+
+        let entities = &mut self.archetypes[archetype].entities;
+        entities.push(entity);
+        let row = entities.len() - 1;
+        self.locations[entity.id] = Some(EntityLocation { archetype, row });
+        row
+    }
+
+    /// Finds or creates the archetype holding exactly `columns`
+    pub(crate) fn archetype_with(
+        &mut self,
+        columns: HashMap<TypeId, Box<dyn TableColumn>>,
+    ) -> ArchetypeId {
+        let mut types = columns.keys().copied().collect::<Vec<_>>();
+        types.sort();
+
+        self.archetype_for(types, EMPTY_ARCHETYPE, |empty| empty.extend(columns))
+    }
+
+    /// Makes room for `additional` more rows in every column of the archetype
+    pub(crate) fn reserve(&mut self, archetype: ArchetypeId, additional: usize) {
+        let archetype = &mut self.archetypes[archetype];
+        archetype.entities.reserve(additional);
+        for column in archetype.columns.values_mut() {
+            column.reserve(additional);
+        }
+    }
+
+    pub(crate) fn archetype_mut(&mut self, archetype: ArchetypeId) -> &mut Archetype {
+        &mut self.archetypes[archetype]
+    }
+
+    /// Deep copy of the tables. Columns of components that don't opt into snapshots are left out,
+    /// their entities end up in the archetype of the remaining columns
+    /// # Safety
+    /// The caller holds the read lock of every table component type
+    pub(crate) unsafe fn snapshot(&self) -> Archetypes {
+        let mut tables = Archetypes::new();
+
+        for archetype in self
+            .archetypes
+            .iter()
+            .filter(|archetype| archetype.len() > 0)
+        {
+            let columns = archetype
+                .columns
+                .iter()
+                .filter_map(|(ty, column)| Some((*ty, column.snapshot()?)))
+                .collect::<Vec<_>>();
+
+            let empty = columns
+                .iter()
+                .map(|(ty, column)| (*ty, column.empty()))
+                .collect();
+            let target = tables.archetype_with(empty);
+
+            for entity in archetype.entities.iter() {
+                tables.spawn_in(*entity, target);
+            }
+
+            let target = &mut tables.archetypes[target];
+            for (ty, column) in columns {
+                target.columns.get_mut(&ty).unwrap().append(column);
+            }
+        }
+
+        tables
+    }
+
+    /// Table components of the entity with their change ticks
+    /// # Safety
+    /// Same as `snapshot`
+    pub(crate) unsafe fn component_ticks(
+        &self,
+        id: usize,
+    ) -> Vec<(TypeId, &'static str, ComponentTicks)> {
+        let Some(location) = self.location(id) else {
+            return vec![];
+        };
+
+        self.archetypes[location.archetype]
+            .columns
+            .iter()
+            .map(|(ty, column)| (*ty, column.component_name(), column.ticks_of(location.row)))
+            .collect()
+    }
+
+    /// Drops the entity's row and all its table components. Returns the types that were dropped
+    pub fn despawn(&mut self, id: usize) -> Vec<TypeId> {
+        self.take_row(id, |column, row| column.swap_remove(row))
+    }
+
+    /// Moves the entity's table components to `entity` in another world and drops its row.
+    /// Returns the types that were moved
+    pub(crate) fn move_entity_to(
+        &mut self,
+        id: usize,
+        target: &mut ComponentsData,
+        entity: EntityID,
+    ) -> Vec<TypeId> {
+        self.take_row(id, |column, row| column.move_to(row, target, entity))
+    }
+
+    /// Hands every column of the entity's row to `take`, which must remove the row
+    fn take_row(
+        &mut self,
+        id: usize,
+        mut take: impl FnMut(&mut dyn TableColumn, usize),
+    ) -> Vec<TypeId> {
+        let Some(location) = self.location(id) else {
+            return vec![];
+        };
+
+        let archetype = &mut self.archetypes[location.archetype];
+        for column in archetype.columns.values_mut() {
+            take(column.as_mut(), location.row);
+        }
+        archetype.entities.swap_remove(location.row);
+
+        if let Some(moved) = archetype.entities.get(location.row) {
+            self.locations[moved.id] = Some(location);
+        }
+        self.locations[id] = None;
+        archetype.types.clone()
+    }
+
+    /// # Safety
+    /// The caller holds the read lock of `T` or has exclusive access to the tables
+    pub unsafe fn get<T: Component>(&self, id: usize) -> Option<&T> {
+        let location = self.location(id)?;
+        self.archetypes[location.archetype]
+            .column::<T>()
+            .map(|column| &*(*column.as_ptr().add(location.row)).get())
+    }
+
+    /// # Safety
+    /// The caller holds the write lock of `T` or has exclusive access to the tables
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_unchecked_mut<T: Component>(
+        &self,
+        id: usize,
+    ) -> Option<(&mut T, &mut ComponentTicks)> {
+        let location = self.location(id)?;
+        self.archetypes[location.archetype]
+            .column::<T>()
+            .map(|column| {
+                (
+                    &mut *(*column.as_ptr().add(location.row)).get(),
+                    &mut *(*column.ticks_ptr().add(location.row)).get(),
+                )
+            })
+    }
+
+    fn get_mut<T: Component>(&mut self, id: usize) -> Option<(&mut T, &mut ComponentTicks)> {
+        let location = self.location(id)?;
+        self.archetypes[location.archetype]
+            .column_mut::<T>()
+            .map(|column| column.get_mut(location.row))
+    }
+
+    /// Moves the entity to the archetype that also has `T`, unless it already has one.
+    /// Returns the previous component in that case
+    pub fn insert<T: Component>(
+        &mut self,
+        entity: EntityID,
+        component: T,
+        tick: usize,
+    ) -> Option<T> {
+        if self.location(entity.id).is_none() {
+            self.spawn(entity);
+        }
+
+        if let Some((current, ticks)) = self.get_mut::<T>(entity.id) {
+            ticks.changed = tick;
+            return Some(std::mem::replace(current, component));
+        }
+
+        let location = self.location(entity.id).unwrap();
+        let mut types = self.archetypes[location.archetype].types.clone();
+        types.push(TypeId::of::<T>());
+        types.sort();
+
+        let target = self.archetype_for(types, location.archetype, |columns| {
+            columns.insert(TypeId::of::<T>(), Box::new(TableData::<T>::new()));
+        });
+        let row = self.move_entity(entity.id, target, None);
+
+        let column = self.archetypes[target].column_mut::<T>().unwrap();
+        column.push(component, ComponentTicks::new(tick));
+        debug_assert_eq!(column.len() - 1, row);
+
+        None
+    }
+
+    /// Moves the entity to the archetype without `T`
+    pub fn remove<T: Component>(&mut self, id: usize) -> Option<T> {
+        let location = self.location(id)?;
+        let component = self.archetypes[location.archetype]
+            .column_mut::<T>()?
+            .take(location.row);
+
+        let types = self.archetypes[location.archetype]
+            .types
+            .iter()
+            .copied()
+            .filter(|ty| *ty != TypeId::of::<T>())
+            .collect();
+
+        let target = self.archetype_for(types, location.archetype, |columns| {
+            columns.remove(&TypeId::of::<T>());
+        });
+        self.move_entity(id, target, Some(TypeId::of::<T>()));
+
+        Some(component)
+    }
+
+    /// Finds the archetype for `types`, creating it from the columns of `template` when missing
+    fn archetype_for(
+        &mut self,
+        types: Vec<TypeId>,
+        template: ArchetypeId,
+        edit_columns: impl FnOnce(&mut HashMap<TypeId, Box<dyn TableColumn>>),
+    ) -> ArchetypeId {
+        if let Some(id) = self.index.get(&types) {
+            return *id;
+        }
+
+        let mut columns = self.archetypes[template]
+            .columns
+            .iter()
+            .map(|(ty, column)| (*ty, column.empty()))
+            .collect();
+        edit_columns(&mut columns);
+
+        let id = self.archetypes.len();
+        self.archetypes.push(Archetype::new(types.clone(), columns));
+        self.index.insert(types, id);
+        id
+    }
+
+    /// Moves the entity's row into `target` and returns the new row.
+    /// Columns missing from the target are dropped, except `taken` whose row the caller already removed
+    fn move_entity(&mut self, id: usize, target: ArchetypeId, taken: Option<TypeId>) -> usize {
+        let location = self.location(id).unwrap();
+        let (source, destination) = pair_mut(&mut self.archetypes, location.archetype, target);
+
+        for (ty, column) in source.columns.iter_mut() {
+            if Some(*ty) == taken {
+                continue;
+            }
+
+            match destination.columns.get_mut(ty) {
+                Some(target_column) => column.move_row(location.row, target_column.as_mut()),
+                None => column.swap_remove(location.row),
+            }
+        }
+
+        let entity = source.entities.swap_remove(location.row);
+        if let Some(moved) = source.entities.get(location.row) {
+            self.locations[moved.id] = Some(location);
+        }
+
+        destination.entities.push(entity);
+        let row = destination.entities.len() - 1;
+        self.locations[id] = Some(EntityLocation {
+            archetype: target,
+            row,
+        });
+        row
+    }
+}
+
+fn pair_mut<T>(items: &mut [T], a: usize, b: usize) -> (&mut T, &mut T) {
+    assert_ne!(a, b);
+
+    if a < b {
+        let (left, right) = items.split_at_mut(b);
+        (&mut left[a], &mut right[0])
+    } else {
+        let (left, right) = items.split_at_mut(a);
+        (&mut right[0], &mut left[b])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Archetypes, EntityLocation, EMPTY_ARCHETYPE};
+    use crate::core::{
+        components::{Component, StorageType},
+        engine::EntityID,
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct Position(i32);
+    #[derive(Debug, PartialEq)]
+    struct Velocity(i32);
+
+    impl Component for Position {
+        const STORAGE: StorageType = StorageType::Table;
+    }
+
+    impl Component for Velocity {
+        const STORAGE: StorageType = StorageType::Table;
+    }
+
+    fn entity(id: usize) -> EntityID {
+        EntityID { id, gen: 0 }
+    }
+
+    #[test]
+    fn inserts_and_removals_move_the_entity() {
+        let mut tables = Archetypes::new();
+        tables.spawn(entity(0));
+        assert_eq!(
+            tables.location(0),
+            Some(EntityLocation {
+                archetype: EMPTY_ARCHETYPE,
+                row: 0
+            })
+        );
+
+        assert_eq!(tables.insert(entity(0), Position(1), 0), None);
+        let with_position = tables.location(0).unwrap().archetype;
+        assert_ne!(with_position, EMPTY_ARCHETYPE);
+
+        assert_eq!(tables.insert(entity(0), Velocity(2), 0), None);
+        let with_both = tables.location(0).unwrap().archetype;
+        assert_ne!(with_both, with_position);
+        unsafe {
+            assert_eq!(tables.get::<Position>(0), Some(&Position(1)));
+            assert_eq!(tables.get::<Velocity>(0), Some(&Velocity(2)));
+        }
+
+        //Inserting a type the entity already has replaces it in place
+        assert_eq!(tables.insert(entity(0), Position(3), 1), Some(Position(1)));
+        assert_eq!(tables.location(0).unwrap().archetype, with_both);
+
+        assert_eq!(tables.remove::<Position>(0), Some(Position(3)));
+        let with_velocity = tables.location(0).unwrap().archetype;
+        assert!(![EMPTY_ARCHETYPE, with_position, with_both].contains(&with_velocity));
+        unsafe {
+            assert_eq!(tables.get::<Position>(0), None);
+            assert_eq!(tables.get::<Velocity>(0), Some(&Velocity(2)));
+        }
+        assert_eq!(tables.remove::<Position>(0), None);
+
+        assert_eq!(tables.remove::<Velocity>(0), Some(Velocity(2)));
+        assert_eq!(tables.location(0).unwrap().archetype, EMPTY_ARCHETYPE);
+    }
+
+    #[test]
+    fn swapped_in_entity_keeps_its_location() {
+        let mut tables = Archetypes::new();
+        for id in 0..3 {
+            tables.spawn(entity(id));
+            tables.insert(entity(id), Position(id as i32), 0);
+        }
+        let positions = tables.location(0).unwrap().archetype;
+
+        //Moving the first row out swaps the last entity into it
+        tables.insert(entity(0), Velocity(0), 0);
+        assert_eq!(
+            tables.location(2),
+            Some(EntityLocation {
+                archetype: positions,
+                row: 0
+            })
+        );
+        assert_eq!(unsafe { tables.get::<Position>(2) }, Some(&Position(2)));
+
+        assert_eq!(tables.despawn(2).len(), 1);
+        assert_eq!(tables.location(2), None);
+        assert_eq!(
+            tables.location(1),
+            Some(EntityLocation {
+                archetype: positions,
+                row: 0
+            })
+        );
+        assert_eq!(unsafe { tables.get::<Position>(1) }, Some(&Position(1)));
+        assert_eq!(unsafe { tables.get::<Position>(0) }, Some(&Position(0)));
+    }
+}
+
+/// Sparse `Vec<Option<T>>` columns against archetype tables for 100k entities.
+/// Run with `cargo test --release -- --ignored --nocapture`
+#[cfg(test)]
+mod benches {
+    use std::time::{Duration, Instant};
+
+    use crate::core::{
+        components::{Component, StorageType},
+        engine::{EntityID, EntityManager},
+    };
+
+    const ENTITIES: usize = 100_000;
+    const RUNS: u32 = 50;
+
+    struct SparsePosition([f32; 3]);
+    struct SparseVelocity([f32; 3]);
+    struct TablePosition([f32; 3]);
+    struct TableVelocity([f32; 3]);
+
+    impl Component for SparsePosition {}
+    impl Component for SparseVelocity {}
+
+    impl Component for TablePosition {
+        const STORAGE: StorageType = StorageType::Table;
+    }
+
+    impl Component for TableVelocity {
+        const STORAGE: StorageType = StorageType::Table;
+    }
+
+    fn average(runs: u32, mut f: impl FnMut()) -> Duration {
+        let start = Instant::now();
+        for _ in 0..runs {
+            f();
+        }
+        start.elapsed() / runs
+    }
+
+    /// Every entity gets a position, every other entity a velocity
+    fn sparse_world() -> EntityManager {
+        let mut entities = EntityManager::new();
+        for i in 0..ENTITIES {
+            let entity = entities.create_entity();
+            let _ = entities.insert_component(entity, SparsePosition([0.0; 3]));
+            if i % 2 == 0 {
+                let _ = entities.insert_component(entity, SparseVelocity([1.0; 3]));
+            }
+        }
+        entities
+    }
+
+    fn table_world() -> EntityManager {
+        let mut entities = EntityManager::new();
+        for i in 0..ENTITIES {
+            let entity = entities.create_entity();
+            let _ = entities.insert_component(entity, TablePosition([0.0; 3]));
+            if i % 2 == 0 {
+                let _ = entities.insert_component(entity, TableVelocity([1.0; 3]));
+            }
+        }
+        entities
+    }
+
+    #[test]
+    #[ignore]
+    fn spawn_100k() {
+        let sparse = average(5, || drop(sparse_world()));
+        let table = average(5, || drop(table_world()));
+
+        println!(
+            "spawn {} entities: sparse {:?}, table {:?}",
+            ENTITIES, sparse, table
+        );
+    }
+
+    /// One `create_entity` and two inserts per entity against a single `spawn_batch`
+    #[test]
+    #[ignore]
+    fn spawn_batch_100k() {
+        let single = average(5, || {
+            let mut entities = EntityManager::new();
+            for _ in 0..ENTITIES {
+                let entity = entities.create_entity();
+                let _ = entities.insert_component(entity, TablePosition([0.0; 3]));
+                let _ = entities.insert_component(entity, TableVelocity([1.0; 3]));
+            }
+        });
+
+        let batch = average(5, || {
+            let mut entities = EntityManager::new();
+            let _ = entities.spawn_batch(
+                (0..ENTITIES).map(|_| (TablePosition([0.0; 3]), TableVelocity([1.0; 3]))),
+            );
+        });
+
+        println!(
+            "spawn {} entities with two table components: one by one {:?}, batch {:?}",
+            ENTITIES, single, batch
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn iterate_100k() {
+        let sparse_entities = sparse_world();
+        let table_entities = table_world();
+
+        let sparse = average(RUNS, || {
+            for (mut position, velocity) in sparse_entities
+                .query::<(&mut SparsePosition, &SparseVelocity)>()
+                .iter()
+            {
+                for axis in 0..3 {
+                    position.0[axis] += velocity.0[axis];
+                }
+            }
+        });
+
+        let table = average(RUNS, || {
+            for (mut position, velocity) in table_entities
+                .query::<(&mut TablePosition, &TableVelocity)>()
+                .iter()
+            {
+                for axis in 0..3 {
+                    position.0[axis] += velocity.0[axis];
+                }
+            }
+        });
+
+        println!(
+            "iterate {} entities: sparse {:?}, table {:?}",
+            ENTITIES, sparse, table
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn add_remove_100k() {
+        let mut sparse_entities = sparse_world();
+        let mut table_entities = table_world();
+
+        let sparse = average(5, || {
+            let handles = sparse_entities
+                .query::<EntityID>()
+                .iter()
+                .collect::<Vec<_>>();
+            for entity in handles {
+                if sparse_entities
+                    .remove_component::<SparseVelocity>(entity)
+                    .is_none()
+                {
+                    let _ = sparse_entities.insert_component(entity, SparseVelocity([1.0; 3]));
+                }
+            }
+        });
+
+        let table = average(5, || {
+            let handles = table_entities
+                .query::<EntityID>()
+                .iter()
+                .collect::<Vec<_>>();
+            for entity in handles {
+                if table_entities
+                    .remove_component::<TableVelocity>(entity)
+                    .is_none()
+                {
+                    let _ = table_entities.insert_component(entity, TableVelocity([1.0; 3]));
+                }
+            }
+        });
+
+        println!(
+            "toggle a component on {} entities: sparse {:?}, table {:?}",
+            ENTITIES, sparse, table
+        );
+    }
+}
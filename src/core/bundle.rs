@@ -0,0 +1,183 @@
+use std::{
+    any::{type_name, TypeId},
+    collections::HashMap,
+};
+
+use super::{
+    archetype::{ArchetypeId, TableColumn, TableData},
+    components::{Component, ComponentsData, StorageType},
+    engine::{EntityError, EntityID, EntityManager},
+};
+
+/// Set of components spawned or inserted together.
+/// Implemented for tuples of up to 8 components, `(T,)` for a single one
+pub trait Bundle: Send + 'static {
+    fn register(components: &mut ComponentsData);
+
+    /// Types of the components in the bundle with their names, in order
+    fn component_types() -> Vec<(TypeId, &'static str)>;
+
+    /// Adds an empty column for each table component of the bundle
+    fn table_columns(columns: &mut HashMap<TypeId, Box<dyn TableColumn>>);
+
+    /// Writes the components of an entity just pushed onto `archetype`, the archetype of the bundle
+    fn write(
+        self,
+        components: &mut ComponentsData,
+        entity: EntityID,
+        archetype: ArchetypeId,
+        tick: usize,
+    );
+
+    /// Inserts the components one by one into an entity that may already have some of them
+    fn insert(self, entities: &mut EntityManager, entity: EntityID) -> Result<(), EntityError>;
+}
+
+/// Fails with the first component type the bundle holds twice.
+/// One value would silently overwrite the other, or land on the next entity's table row
+pub(crate) fn check_duplicates<B: Bundle>() -> Result<(), EntityError> {
+    let types = B::component_types();
+
+    for (index, (ty, name)) in types.iter().enumerate() {
+        if types[..index].iter().any(|(other, _)| other == ty) {
+            return Err(EntityError::DuplicateComponent(name));
+        }
+    }
+
+    Ok(())
+}
+
+/// Table column for `T` if it is a table component.
+/// The bundle was checked for duplicates beforehand
+fn table_column<T: Component>(columns: &mut HashMap<TypeId, Box<dyn TableColumn>>) {
+    if T::STORAGE == StorageType::Table {
+        columns.insert(TypeId::of::<T>(), Box::new(TableData::<T>::new()));
+    }
+}
+
+macro_rules! impl_bundle_tuple {
+    ($($name:ident),*) => {
+        #[allow(non_snake_case)]
+        impl<$($name: Component),*> Bundle for ($($name,)*) {
+            fn register(components: &mut ComponentsData) {
+                $(components.register::<$name>();)*
+            }
+
+            fn component_types() -> Vec<(TypeId, &'static str)> {
+                vec![$((TypeId::of::<$name>(), type_name::<$name>())),*]
+            }
+
+            fn table_columns(columns: &mut HashMap<TypeId, Box<dyn TableColumn>>) {
+                $(table_column::<$name>(columns);)*
+            }
+
+            fn write(
+                self,
+                components: &mut ComponentsData,
+                entity: EntityID,
+                archetype: ArchetypeId,
+                tick: usize,
+            ) {
+                let ($($name,)*) = self;
+                $(components.write_spawned(entity, archetype, $name, tick);)*
+            }
+
+            fn insert(self, entities: &mut EntityManager, entity: EntityID) -> Result<(), EntityError> {
+                let ($($name,)*) = self;
+                $(entities.insert_component(entity, $name)?;)*
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_bundle_tuple!(A);
+impl_bundle_tuple!(A, B);
+impl_bundle_tuple!(A, B, C);
+impl_bundle_tuple!(A, B, C, D);
+impl_bundle_tuple!(A, B, C, D, E);
+impl_bundle_tuple!(A, B, C, D, E, F);
+impl_bundle_tuple!(A, B, C, D, E, F, G);
+impl_bundle_tuple!(A, B, C, D, E, F, G, H);
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{
+        components::{Component, StorageType},
+        engine::{EntityError, EntityManager},
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct Speed(i32);
+    #[derive(Debug, PartialEq)]
+    struct Position(i32);
+
+    impl Component for Speed {}
+
+    impl Component for Position {
+        const STORAGE: StorageType = StorageType::Table;
+    }
+
+    #[test]
+    fn duplicate_components_are_rejected() {
+        let mut entities = EntityManager::new();
+        let entity = entities.create_entity();
+
+        assert_eq!(
+            entities.spawn_bundle((Position(1), Speed(1), Position(2))),
+            Err(EntityError::DuplicateComponent(std::any::type_name::<
+                Position,
+            >()))
+        );
+        assert_eq!(
+            entities.spawn_batch([(Speed(1), Speed(2))]),
+            Err(EntityError::DuplicateComponent(
+                std::any::type_name::<Speed>()
+            ))
+        );
+        assert_eq!(
+            entities.insert_bundle(entity, (Speed(1), Speed(2))),
+            Err(EntityError::DuplicateComponent(
+                std::any::type_name::<Speed>()
+            ))
+        );
+
+        //Nothing was spawned or inserted
+        assert_eq!(entities.entities().collect::<Vec<_>>(), [entity]);
+        assert!(!entities.has_component::<Speed>(entity));
+    }
+
+    #[test]
+    fn batches_reuse_deleted_slots_first() {
+        let mut entities = EntityManager::new();
+        let old = entities
+            .spawn_batch((0..3).map(|i| (Speed(i), Position(i))))
+            .unwrap();
+        assert!(entities.destroy_entity(old[0]));
+        assert!(entities.destroy_entity(old[2]));
+
+        let new = entities
+            .spawn_batch((3..6).map(|i| (Speed(i), Position(i))))
+            .unwrap();
+
+        //Freed slots come back with a new generation, the rest is appended
+        assert_eq!(new[0].id(), old[0].id());
+        assert_eq!(new[0].gen(), old[0].gen() + 1);
+        assert_eq!(new[1].id(), old[2].id());
+        assert_eq!(new[2].id(), 3);
+        assert!(!entities.is_alive(old[0]));
+        assert!(entities.get_component::<Speed>(old[2]).is_none());
+
+        for (entity, i) in new.iter().zip(3..) {
+            assert_eq!(*entities.get_component::<Speed>(*entity).unwrap(), Speed(i));
+            assert_eq!(
+                *entities.get_component::<Position>(*entity).unwrap(),
+                Position(i)
+            );
+        }
+        assert_eq!(
+            *entities.get_component::<Position>(old[1]).unwrap(),
+            Position(1)
+        );
+    }
+}
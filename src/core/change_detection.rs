@@ -0,0 +1,313 @@
+use std::{
+    cell::Cell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use super::{
+    archetype::Archetype,
+    components::{Column, Component, ComponentsData},
+    engine::EntityID,
+    query::{Access, ComponentFetch, QueryFilter},
+};
+
+/// Change ticks of a single component
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentTicks {
+    pub added: usize,
+    pub changed: usize,
+}
+
+impl ComponentTicks {
+    pub(crate) fn new(tick: usize) -> Self {
+        Self {
+            added: tick,
+            changed: tick,
+        }
+    }
+
+    pub fn is_added(&self, last_run: usize) -> bool {
+        self.added >= last_run
+    }
+
+    pub fn is_changed(&self, last_run: usize) -> bool {
+        self.changed >= last_run
+    }
+}
+
+thread_local! {
+    static LAST_RUN: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Ticks a query compares against and stamps writes with.
+/// `ComponentsData::change_tick` is bumped every time a system starts its step,
+/// anything stamped at or after the system's previous start is new to it.
+/// A change made while the system itself was running may be reported on two runs, never on none
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SystemTicks {
+    pub last_run: usize,
+    pub this_run: usize,
+}
+
+impl SystemTicks {
+    /// Outside of a system `last_run` is 0 and everything counts as added and changed
+    pub(crate) fn current(components: &ComponentsData) -> Self {
+        Self {
+            last_run: LAST_RUN.with(Cell::get),
+            this_run: components.change_tick(),
+        }
+    }
+}
+
+/// Runs `f` as the step of a system that last started at `last_run`.
+/// Queries created inside `f` on this thread compare against it.
+/// The previous tick is restored even if `f` panics
+pub(crate) fn run_system<R>(last_run: usize, f: impl FnOnce() -> R) -> R {
+    let _restore = RestoreLastRun(LAST_RUN.with(|cell| cell.replace(last_run)));
+    f()
+}
+
+/// Puts back the `LAST_RUN` of the enclosing system when dropped
+struct RestoreLastRun(usize);
+
+impl Drop for RestoreLastRun {
+    fn drop(&mut self) {
+        LAST_RUN.with(|cell| cell.set(self.0));
+    }
+}
+
+/// Mutable borrow of a component that marks it changed once it is written through
+pub struct Mut<'w, T> {
+    value: &'w mut T,
+    component_ticks: &'w mut ComponentTicks,
+    ticks: SystemTicks,
+}
+
+impl<'w, T> Mut<'w, T> {
+    pub(crate) fn new(
+        value: &'w mut T,
+        component_ticks: &'w mut ComponentTicks,
+        ticks: SystemTicks,
+    ) -> Self {
+        Self {
+            value,
+            component_ticks,
+            ticks,
+        }
+    }
+
+    pub fn is_added(&self) -> bool {
+        self.component_ticks.is_added(self.ticks.last_run)
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.component_ticks.is_changed(self.ticks.last_run)
+    }
+
+    /// Writes without marking the component changed
+    pub fn bypass_change_detection(&mut self) -> &mut T {
+        self.value
+    }
+}
+
+impl<'w, T> Deref for Mut<'w, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.value
+    }
+}
+
+impl<'w, T> DerefMut for Mut<'w, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.component_ticks.changed = self.ticks.this_run;
+        self.value
+    }
+}
+
+/// Only entities whose `T` was added since the system last ran
+pub struct Added<T>(PhantomData<T>);
+
+/// Only entities whose `T` was added or written to since the system last ran
+pub struct Changed<T>(PhantomData<T>);
+
+impl<T: Component> QueryFilter for Added<T> {
+    type State = (Option<*const Column<T>>, SystemTicks);
+    type Fetch = (ComponentFetch<T>, SystemTicks);
+
+    fn access(access: &mut Access) {
+        access.add_filter::<T>();
+    }
+
+    fn init_state(components: &ComponentsData) -> Option<Self::State> {
+        Some((
+            ComponentFetch::init_state(components)?,
+            SystemTicks::current(components),
+        ))
+    }
+
+    fn set_archetype(state: &Self::State, archetype: &Archetype) -> Option<Self::Fetch> {
+        Some((ComponentFetch::new(&state.0, archetype)?, state.1))
+    }
+
+    fn matches((fetch, ticks): &Self::Fetch, entity: EntityID, row: usize) -> bool {
+        fetch.matches(entity) && unsafe { fetch.ticks(entity, row) }.is_added(ticks.last_run)
+    }
+}
+
+impl<T: Component> QueryFilter for Changed<T> {
+    type State = (Option<*const Column<T>>, SystemTicks);
+    type Fetch = (ComponentFetch<T>, SystemTicks);
+
+    fn access(access: &mut Access) {
+        access.add_filter::<T>();
+    }
+
+    fn init_state(components: &ComponentsData) -> Option<Self::State> {
+        Some((
+            ComponentFetch::init_state(components)?,
+            SystemTicks::current(components),
+        ))
+    }
+
+    fn set_archetype(state: &Self::State, archetype: &Archetype) -> Option<Self::Fetch> {
+        Some((ComponentFetch::new(&state.0, archetype)?, state.1))
+    }
+
+    fn matches((fetch, ticks): &Self::Fetch, entity: EntityID, row: usize) -> bool {
+        fetch.matches(entity) && unsafe { fetch.ticks(entity, row) }.is_changed(ticks.last_run)
+    }
+}
+
+/// Entities that lost their `T`, or were destroyed with it, since the system last ran.
+/// Created through `EntityManager::removed`
+pub struct RemovedComponents<'w, T> {
+    removed: std::slice::Iter<'w, (EntityID, usize)>,
+    last_run: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<'w, T: Component> RemovedComponents<'w, T> {
+    pub(crate) fn new(removed: &'w [(EntityID, usize)], last_run: usize) -> Self {
+        Self {
+            removed: removed.iter(),
+            last_run,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'w, T: Component> Iterator for RemovedComponents<'w, T> {
+    type Item = EntityID;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let last_run = self.last_run;
+        self.removed
+            .find(|(_, tick)| *tick >= last_run)
+            .map(|(entity, _)| *entity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::catch_unwind;
+
+    use super::{run_system, Added, Changed, LAST_RUN};
+    use crate::core::{
+        components::{Component, StorageType},
+        engine::{EntityID, EntityManager},
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct Speed(i32);
+    #[derive(Debug, PartialEq)]
+    struct Position(i32);
+
+    impl Component for Speed {}
+
+    impl Component for Position {
+        const STORAGE: StorageType = StorageType::Table;
+    }
+
+    /// Runs `f` the way the engine runs a system step and returns the tick of the next run
+    fn step(entities: &EntityManager, last_run: usize, f: impl FnOnce()) -> usize {
+        let this_run = entities.increment_change_tick();
+        run_system(last_run, f);
+        this_run
+    }
+
+    fn added<T: Component>(entities: &EntityManager) -> Vec<EntityID> {
+        let mut query = entities.query_filtered::<EntityID, Added<T>>();
+        query.iter().collect()
+    }
+
+    fn changed<T: Component>(entities: &EntityManager) -> Vec<EntityID> {
+        let mut query = entities.query_filtered::<EntityID, Changed<T>>();
+        query.iter().collect()
+    }
+
+    #[test]
+    fn added_and_changed_since_the_last_run() {
+        let mut entities = EntityManager::new();
+        let a = entities.create_entity();
+        let b = entities.create_entity();
+        entities.insert_component(a, Speed(1)).unwrap();
+        entities.insert_component(a, Position(1)).unwrap();
+        entities.insert_component(b, Position(2)).unwrap();
+
+        //The first run sees everything
+        let last_run = step(&entities, 0, || {
+            assert_eq!(added::<Speed>(&entities), [a]);
+            assert_eq!(changed::<Position>(&entities), [a, b]);
+        });
+
+        entities.insert_component(b, Speed(2)).unwrap();
+        entities.get_component_mut::<Position>(a).unwrap().0 += 1;
+        //Reading through a mutable borrow doesn't count as a change
+        assert_eq!(entities.get_component_mut::<Position>(b).unwrap().0, 2);
+
+        let last_run = step(&entities, last_run, || {
+            assert_eq!(added::<Speed>(&entities), [b]);
+            assert_eq!(changed::<Speed>(&entities), [b]);
+            assert_eq!(added::<Position>(&entities), []);
+            assert_eq!(changed::<Position>(&entities), [a]);
+        });
+
+        step(&entities, last_run, || {
+            assert_eq!(changed::<Speed>(&entities), []);
+            assert_eq!(changed::<Position>(&entities), []);
+        });
+    }
+
+    #[test]
+    fn removals_are_seen_once() {
+        let mut entities = EntityManager::new();
+        let a = entities.create_entity();
+        let b = entities.create_entity();
+        entities.insert_component(a, Speed(1)).unwrap();
+        entities.insert_component(b, Speed(2)).unwrap();
+
+        let last_run = step(&entities, 0, || {
+            assert_eq!(entities.removed::<Speed>().count(), 0);
+        });
+
+        entities.remove_component::<Speed>(a).unwrap();
+        assert!(entities.destroy_entity(b));
+
+        let last_run = step(&entities, last_run, || {
+            assert_eq!(entities.removed::<Speed>().collect::<Vec<_>>(), [a, b]);
+        });
+
+        step(&entities, last_run, || {
+            assert_eq!(entities.removed::<Speed>().count(), 0);
+        });
+    }
+
+    #[test]
+    fn panicking_step_restores_the_last_run() {
+        let result = catch_unwind(|| run_system(5, || panic!("step failed")));
+
+        assert!(result.is_err());
+        assert_eq!(LAST_RUN.with(|cell| cell.get()), 0);
+    }
+}
@@ -0,0 +1,362 @@
+use std::sync::Arc;
+
+use super::{
+    bundle::Bundle,
+    components::Component,
+    engine::{EntityError, EntityID, EntityManager},
+    prefab::Prefab,
+    relations::Relation,
+    resources::Resource,
+};
+
+/// Entity a command applies to. Entities spawned through `Commands` only get
+/// their `EntityID` once the buffer is applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandTarget {
+    Existing(EntityID),
+    /// Index of the spawn within the buffer
+    Spawned(usize),
+}
+
+type EntityEdit = Box<dyn FnOnce(&mut EntityManager, EntityID) -> Result<(), EntityError> + Send>;
+type WorldEdit = Box<dyn FnOnce(&mut EntityManager) -> Result<(), EntityError> + Send>;
+
+enum Command {
+    /// Spawns an empty entity or an instance of the prefab
+    Spawn(Option<Arc<Prefab>>),
+    /// Changes that don't target a single entity
+    World(WorldEdit),
+    AddPrefab(Arc<Prefab>),
+    Despawn(CommandTarget),
+    Edit(CommandTarget, EntityEdit),
+}
+
+/// Structural changes recorded by a system during `step`.
+/// The engine applies every buffer at the sync point in `Engine::update`,
+/// in the order the commands were recorded
+#[derive(Default)]
+pub struct Commands {
+    queue: Vec<Command>,
+    spawned: usize,
+}
+
+impl Commands {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn spawn(&mut self) -> EntityCommands<'_> {
+        self.push_spawn(None)
+    }
+
+    /// Components inserted through the returned `EntityCommands` override the prefab's
+    pub fn spawn_prefab(&mut self, prefab: Arc<Prefab>) -> EntityCommands<'_> {
+        self.push_spawn(Some(prefab))
+    }
+
+    fn push_spawn(&mut self, prefab: Option<Arc<Prefab>>) -> EntityCommands<'_> {
+        let target = CommandTarget::Spawned(self.spawned);
+        self.spawned += 1;
+        self.queue.push(Command::Spawn(prefab));
+
+        EntityCommands {
+            commands: self,
+            target,
+        }
+    }
+
+    /// Spawns one entity per bundle, see `EntityManager::spawn_batch`.
+    /// The entities can't be targeted by later commands of the same buffer
+    pub fn spawn_batch<B: Bundle>(&mut self, bundles: impl IntoIterator<Item = B>) {
+        let bundles = bundles.into_iter().collect::<Vec<_>>();
+        self.queue.push(Command::World(Box::new(move |entities| {
+            entities.spawn_batch(bundles).map(|_| ())
+        })));
+    }
+
+    pub fn insert_resource<T: Resource>(&mut self, resource: T) {
+        self.queue.push(Command::World(Box::new(move |entities| {
+            entities.insert_resource(resource);
+            Ok(())
+        })));
+    }
+
+    /// Registers the prefab, see `EntityManager::add_prefab`
+    pub fn add_prefab(&mut self, prefab: Arc<Prefab>) {
+        self.queue.push(Command::AddPrefab(prefab));
+    }
+
+    pub fn entity(&mut self, entity: EntityID) -> EntityCommands<'_> {
+        EntityCommands {
+            commands: self,
+            target: CommandTarget::Existing(entity),
+        }
+    }
+
+    pub fn despawn(&mut self, entity: EntityID) {
+        self.entity(entity).despawn();
+    }
+
+    pub fn insert<T: Component>(&mut self, entity: EntityID, component: T) {
+        self.entity(entity).insert(component);
+    }
+
+    pub fn remove<T: Component>(&mut self, entity: EntityID) {
+        self.entity(entity).remove::<T>();
+    }
+
+    /// Runs the recorded commands and empties the buffer.
+    /// Commands on entities that died in the meantime are skipped and reported
+    pub(crate) fn apply(&mut self, entities: &mut EntityManager) -> Vec<EntityError> {
+        let mut spawned = Vec::with_capacity(self.spawned);
+        let mut errors = vec![];
+
+        for command in self.queue.drain(..) {
+            let result = match command {
+                Command::Spawn(prefab) => {
+                    let entity = match prefab {
+                        Some(prefab) => entities.spawn_prefab(&prefab),
+                        None => entities.try_create_entity(),
+                    };
+                    spawned.push(entity.ok());
+                    entity.map(|_| ())
+                }
+                Command::World(edit) => edit(entities),
+                Command::AddPrefab(prefab) => {
+                    entities.add_prefab(prefab);
+                    Ok(())
+                }
+                Command::Despawn(target) => resolve(target, &spawned).and_then(|entity| {
+                    if entities.destroy_entity(entity) {
+                        Ok(())
+                    } else {
+                        Err(EntityError::DeadEntity(entity))
+                    }
+                }),
+                Command::Edit(target, edit) => {
+                    resolve(target, &spawned).and_then(|entity| edit(entities, entity))
+                }
+            };
+
+            if let Err(error) = result {
+                errors.push(error);
+            }
+        }
+
+        self.spawned = 0;
+        errors
+    }
+}
+
+/// A spawn that failed leaves no entity behind for the commands that follow it
+fn resolve(target: CommandTarget, spawned: &[Option<EntityID>]) -> Result<EntityID, EntityError> {
+    match target {
+        CommandTarget::Existing(entity) => Ok(entity),
+        CommandTarget::Spawned(index) => spawned
+            .get(index)
+            .copied()
+            .flatten()
+            .ok_or(EntityError::UnresolvedSpawn(index)),
+    }
+}
+
+pub struct EntityCommands<'a> {
+    commands: &'a mut Commands,
+    target: CommandTarget,
+}
+
+impl<'a> EntityCommands<'a> {
+    pub fn target(&self) -> CommandTarget {
+        self.target
+    }
+
+    pub fn insert<T: Component>(&mut self, component: T) -> &mut Self {
+        let edit: EntityEdit = Box::new(move |entities, entity| {
+            entities.insert_component(entity, component).map(|_| ())
+        });
+        self.commands.queue.push(Command::Edit(self.target, edit));
+        self
+    }
+
+    pub fn insert_bundle<B: Bundle>(&mut self, bundle: B) -> &mut Self {
+        let edit: EntityEdit =
+            Box::new(move |entities, entity| entities.insert_bundle(entity, bundle));
+        self.commands.queue.push(Command::Edit(self.target, edit));
+        self
+    }
+
+    pub fn remove<T: Component>(&mut self) -> &mut Self {
+        let edit: EntityEdit = Box::new(|entities, entity| {
+            if !entities.is_alive(entity) {
+                return Err(EntityError::DeadEntity(entity));
+            }
+
+            entities.remove_component::<T>(entity);
+            Ok(())
+        });
+        self.commands.queue.push(Command::Edit(self.target, edit));
+        self
+    }
+
+    /// Attaches the entity to `parent`, see `EntityManager::set_parent`
+    pub fn set_parent(&mut self, parent: EntityID) -> &mut Self {
+        let edit: EntityEdit =
+            Box::new(move |entities, entity| entities.set_parent(entity, parent));
+        self.commands.queue.push(Command::Edit(self.target, edit));
+        self
+    }
+
+    /// Relates the entity to `target`, see `EntityManager::add_relation`
+    pub fn add_relation<R: Relation>(&mut self, target: EntityID) -> &mut Self {
+        let edit: EntityEdit = Box::new(move |entities, entity| {
+            entities.add_relation::<R>(entity, target).map(|_| ())
+        });
+        self.commands.queue.push(Command::Edit(self.target, edit));
+        self
+    }
+
+    pub fn remove_relation<R: Relation>(&mut self, target: EntityID) -> &mut Self {
+        let edit: EntityEdit = Box::new(move |entities, entity| {
+            entities.remove_relation::<R>(entity, target);
+            Ok(())
+        });
+        self.commands.queue.push(Command::Edit(self.target, edit));
+        self
+    }
+
+    /// Names the entity, see `EntityManager::set_name`
+    pub fn set_name(&mut self, name: impl Into<String>) -> &mut Self {
+        let name = name.into();
+        let edit: EntityEdit = Box::new(move |entities, entity| entities.set_name(entity, name));
+        self.commands.queue.push(Command::Edit(self.target, edit));
+        self
+    }
+
+    /// Despawns the entity and all of its descendants
+    pub fn despawn_recursive(&mut self) {
+        let edit: EntityEdit = Box::new(|entities, entity| {
+            if entities.destroy_entity_recursive(entity) {
+                Ok(())
+            } else {
+                Err(EntityError::DeadEntity(entity))
+            }
+        });
+        self.commands.queue.push(Command::Edit(self.target, edit));
+    }
+
+    pub fn despawn(&mut self) {
+        self.commands.queue.push(Command::Despawn(self.target));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CommandTarget, Commands};
+    use crate::core::{
+        components::Component,
+        engine::{EntityError, EntityID, EntityManager},
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct Speed(i32);
+    impl Component for Speed {}
+
+    fn speed(entities: &EntityManager, entity: EntityID) -> Option<i32> {
+        entities.get_component::<Speed>(entity).map(|speed| speed.0)
+    }
+
+    #[test]
+    fn spawned_targets_resolve_to_their_spawn() {
+        let mut entities = EntityManager::new();
+        let existing = entities.create_entity();
+        let mut commands = Commands::new();
+
+        assert_eq!(commands.spawn().target(), CommandTarget::Spawned(0));
+        let second = commands.spawn().insert(Speed(2)).target();
+        assert_eq!(second, CommandTarget::Spawned(1));
+        commands.spawn().insert(Speed(3));
+        assert_eq!(
+            commands.entity(existing).insert(Speed(1)).target(),
+            CommandTarget::Existing(existing)
+        );
+
+        assert!(commands.apply(&mut entities).is_empty());
+        assert!(commands.is_empty());
+
+        let speeds = entities
+            .entities()
+            .map(|entity| speed(&entities, entity))
+            .collect::<Vec<_>>();
+        assert_eq!(speeds, [Some(1), None, Some(2), Some(3)]);
+
+        //Spawn indices start over with the next buffer
+        assert_eq!(commands.spawn().target(), CommandTarget::Spawned(0));
+    }
+
+    #[test]
+    fn failed_spawns_leave_their_commands_unresolved() {
+        let mut entities = EntityManager::with_entity_limit(Some(1));
+        let mut commands = Commands::new();
+
+        commands.spawn().insert(Speed(1));
+        commands.spawn().insert(Speed(2)).despawn();
+
+        assert_eq!(
+            commands.apply(&mut entities),
+            [
+                EntityError::LimitReached(1),
+                EntityError::UnresolvedSpawn(1),
+                EntityError::UnresolvedSpawn(1),
+            ]
+        );
+        assert_eq!(entities.entities().count(), 1);
+    }
+
+    #[test]
+    fn edits_of_despawned_entities_are_reported() {
+        let mut entities = EntityManager::new();
+        let entity = entities.create_entity();
+        entities.destroy_entity(entity);
+        let mut commands = Commands::new();
+
+        commands.insert(entity, Speed(1));
+        commands.remove::<Speed>(entity);
+        commands.despawn(entity);
+
+        assert_eq!(
+            commands.apply(&mut entities),
+            [EntityError::DeadEntity(entity); 3]
+        );
+    }
+
+    #[test]
+    fn commands_apply_in_recording_order() {
+        let mut entities = EntityManager::new();
+        let kept = entities.create_entity();
+        let despawned = entities.create_entity();
+        let mut commands = Commands::new();
+
+        commands
+            .entity(kept)
+            .insert(Speed(1))
+            .remove::<Speed>()
+            .insert(Speed(2));
+        commands.despawn(despawned);
+        commands.insert(despawned, Speed(3));
+
+        assert_eq!(
+            commands.apply(&mut entities),
+            [EntityError::DeadEntity(despawned)]
+        );
+        assert_eq!(speed(&entities, kept), Some(2));
+        assert!(!entities.is_alive(despawned));
+    }
+}
@@ -1,19 +1,787 @@
-use super::engine::TOTAL_ENTITIES;
-
-pub trait Component {}
-
-pub struct ComponentsData {
-    render_components: Vec<Option<RenderComponent>>,
-}
-
-pub struct RenderComponent {}
-pub struct TransformComponent {}
-
-
-impl ComponentsData {
-	pub fn new() -> Self {
-		Self {
-			render_components: Vec::with_capacity(TOTAL_ENTITIES)
-		}
-	}
-}
\ No newline at end of file
+use std::{
+    any::{type_name, Any, TypeId},
+    cell::{RefCell, UnsafeCell},
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+};
+
+use nalgebra::{Matrix4, UnitQuaternion, Vector3};
+
+use super::{
+    archetype::{ArchetypeId, Archetypes},
+    bundle::Bundle,
+    change_detection::{ComponentTicks, Mut, RemovedComponents, SystemTicks},
+    engine::EntityID,
+    paged_vec::PagedVec,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageType {
+    /// One `Option<T>` slot per entity. Cheap to add and remove
+    Sparse,
+    /// Packed in archetype tables. Fast to iterate, adding and removing moves the entity
+    Table,
+}
+
+pub trait Component: Any + Send + Sync {
+    const STORAGE: StorageType = StorageType::Sparse;
+
+    /// Copy of the component for `EntityManager::snapshot`, usually `Some(self.clone())`.
+    /// Components that don't override it are left out of snapshots
+    fn snapshot(&self) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+}
+
+/// Type erased access to a single component column.
+/// Lets `ComponentsData` keep columns of different component types side by side.
+pub trait ComponentColumn: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// Drops the component stored for the entity slot. Returns false if there was none
+    fn remove_entity(&mut self, id: usize) -> bool;
+    /// Takes the component out of the slot and inserts it into the entity of another world.
+    /// Returns false if there was none
+    fn move_to(&mut self, id: usize, target: &mut ComponentsData, entity: EntityID) -> bool;
+    fn component_name(&self) -> &'static str;
+    /// # Safety
+    /// The caller holds the lock of the component type or has exclusive access to the column
+    unsafe fn ticks_of(&self, id: usize) -> Option<ComponentTicks>;
+    /// Deep copy through `Component::snapshot`, None if the component type doesn't opt in
+    /// # Safety
+    /// Same as `ticks_of`
+    unsafe fn snapshot(&self) -> Option<Box<dyn ComponentColumn>>;
+}
+
+/// One slot per entity, indexed by `EntityID::id`.
+/// Slots are paged so growing the column never moves existing components
+pub struct Column<T: Component> {
+    data: PagedVec<Option<UnsafeCell<T>>>,
+    ticks: PagedVec<UnsafeCell<ComponentTicks>>,
+}
+
+//Shared access to the components goes through the lock of `T` in `ComponentsData`
+unsafe impl<T: Component> Sync for Column<T> {}
+
+impl<T: Component> Column<T> {
+    fn new() -> Self {
+        Self {
+            data: PagedVec::new(),
+            ticks: PagedVec::new(),
+        }
+    }
+
+    /// Replacing a component marks it changed, not added
+    fn insert(&mut self, id: usize, component: T, tick: usize) -> Option<T> {
+        if id >= self.data.len() {
+            self.data.resize_with(id + 1, || None);
+            self.ticks.resize_with(id + 1, Default::default);
+        }
+
+        let previous = self.data[id]
+            .replace(UnsafeCell::new(component))
+            .map(UnsafeCell::into_inner);
+
+        let ticks = self.ticks[id].get_mut();
+        match previous {
+            Some(_) => ticks.changed = tick,
+            None => *ticks = ComponentTicks::new(tick),
+        }
+
+        previous
+    }
+
+    pub(crate) fn contains(&self, id: usize) -> bool {
+        matches!(self.data.get(id), Some(Some(_)))
+    }
+
+    /// # Safety
+    /// The caller holds the read lock of `T` or has exclusive access to the column
+    pub(crate) unsafe fn get(&self, id: usize) -> Option<&T> {
+        match self.data.get(id) {
+            Some(Some(component)) => Some(&*component.get()),
+            _ => None,
+        }
+    }
+
+    /// # Safety
+    /// The caller holds the write lock of `T` or has exclusive access to the column
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn get_unchecked_mut(
+        &self,
+        id: usize,
+    ) -> Option<(&mut T, &mut ComponentTicks)> {
+        match self.data.get(id) {
+            Some(Some(component)) => Some((&mut *component.get(), &mut *self.ticks[id].get())),
+            _ => None,
+        }
+    }
+
+    /// # Safety
+    /// Same as `get`, the slot must hold a component
+    pub(crate) unsafe fn ticks(&self, id: usize) -> &ComponentTicks {
+        &*self.ticks[id].get()
+    }
+
+    fn remove(&mut self, id: usize) -> Option<T> {
+        self.data
+            .get_mut(id)
+            .and_then(|c| c.take())
+            .map(UnsafeCell::into_inner)
+    }
+}
+
+impl<T: Component> ComponentColumn for Column<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn remove_entity(&mut self, id: usize) -> bool {
+        self.remove(id).is_some()
+    }
+
+    fn move_to(&mut self, id: usize, target: &mut ComponentsData, entity: EntityID) -> bool {
+        let Some(component) = self.remove(id) else {
+            return false;
+        };
+
+        target.insert(entity, component);
+        true
+    }
+
+    fn component_name(&self) -> &'static str {
+        type_name::<T>()
+    }
+
+    unsafe fn ticks_of(&self, id: usize) -> Option<ComponentTicks> {
+        self.contains(id).then(|| *self.ticks(id))
+    }
+
+    unsafe fn snapshot(&self) -> Option<Box<dyn ComponentColumn>> {
+        let mut column = Column::<T>::new();
+
+        for id in 0..self.data.len() {
+            if let Some(component) = self.get(id) {
+                column.insert(id, component.snapshot()?, 0);
+                *column.ticks[id].get_mut() = *self.ticks(id);
+            }
+        }
+
+        Some(Box::new(column))
+    }
+}
+
+thread_local! {
+    /// Component and resource locks held by the thread,
+    /// with the address of the `ComponentsData` or `Resources` they belong to
+    static HELD_LOCKS: RefCell<Vec<(usize, TypeId)>> = const { RefCell::new(vec![]) };
+}
+
+/// Entry of the thread's held locks, removed when dropped.
+/// Taken before the lock itself so borrowing a type twice panics instead of deadlocking
+pub(crate) struct HeldLock {
+    owner: usize,
+    ty: TypeId,
+}
+
+impl HeldLock {
+    /// Panics if the thread already holds the lock of `ty` in `owner`
+    pub(crate) fn new(owner: usize, ty: TypeId, name: &str) -> Self {
+        HELD_LOCKS.with(|locks| {
+            let mut locks = locks.borrow_mut();
+            if locks.contains(&(owner, ty)) {
+                panic!(
+                    "{} is already locked by this thread. Drop the query or reference \
+                     holding it before borrowing it again",
+                    name
+                );
+            }
+            locks.push((owner, ty));
+        });
+
+        Self { owner, ty }
+    }
+}
+
+impl Drop for HeldLock {
+    fn drop(&mut self) {
+        let held = (self.owner, self.ty);
+        HELD_LOCKS.with(|locks| {
+            let mut locks = locks.borrow_mut();
+            if let Some(index) = locks.iter().rposition(|lock| *lock == held) {
+                locks.swap_remove(index);
+            }
+        });
+    }
+}
+
+enum LockGuard<'a> {
+    Read(RwLockReadGuard<'a, ()>),
+    Write(RwLockWriteGuard<'a, ()>),
+}
+
+/// Held by queries and component references for as long as they borrow a component type
+pub struct ComponentGuard<'a> {
+    _guard: LockGuard<'a>,
+    _held: HeldLock,
+}
+
+pub struct ComponentRef<'a, T> {
+    _guard: ComponentGuard<'a>,
+    value: &'a T,
+}
+
+impl<'a, T> Deref for ComponentRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.value
+    }
+}
+
+pub struct ComponentMut<'a, T> {
+    _guard: ComponentGuard<'a>,
+    value: Mut<'a, T>,
+}
+
+impl<'a, T> ComponentMut<'a, T> {
+    /// Writes without marking the component changed
+    pub fn bypass_change_detection(&mut self) -> &mut T {
+        self.value.bypass_change_detection()
+    }
+}
+
+impl<'a, T> Deref for ComponentMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<'a, T> DerefMut for ComponentMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
+/// Every component type has its own lock so systems touching different
+/// components can run at the same time behind a shared `EntityManager` read lock.
+/// Structural changes (insert, remove, spawn, destroy) still need `&mut`
+pub struct ComponentsData {
+    columns: HashMap<TypeId, Box<dyn ComponentColumn>>,
+    pub(crate) tables: Archetypes,
+    /// Lock of each component type with its name
+    locks: HashMap<TypeId, (RwLock<()>, &'static str)>,
+    /// Bumped every time a system starts its step, see `SystemTicks`
+    change_tick: AtomicUsize,
+    /// Entities that lost a component, with the tick it happened at
+    removed: HashMap<TypeId, Vec<(EntityID, usize)>>,
+}
+
+pub struct RenderComponent {}
+
+/// Unique name of an entity, set through `EntityManager::set_name`
+/// so that `EntityManager::find_by_name` can find it.
+/// Inserting it like any other component, in a bundle or through commands, keeps the index too.
+/// Not `Clone` so prefabs can't copy one name onto every instance, see `Overrides::with_name`
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct Name(pub(crate) String);
+
+impl Name {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Declares zero sized tag components.
+/// Tags are stored in tables so `With<Tag>` skips whole archetypes instead of checking every entity
+/// ```ignore
+/// tags!(Player, MainLight);
+/// entities.insert_component(entity, Player)?;
+/// ```
+#[macro_export]
+macro_rules! tags {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+            pub struct $name;
+
+            impl $crate::core::components::Component for $name {
+                const STORAGE: $crate::core::components::StorageType =
+                    $crate::core::components::StorageType::Table;
+
+                fn snapshot(&self) -> Option<Self> {
+                    Some(*self)
+                }
+            }
+        )*
+    };
+}
+
+/// Position of the entity relative to its parent, or to the world for root entities.
+/// The world matrix is recomputed from the hierarchy every tick, see `hierarchy::propagate_transforms`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransformComponent {
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+    world_matrix: Matrix4<f32>,
+}
+
+impl TransformComponent {
+    pub fn new(
+        translation: Vector3<f32>,
+        rotation: UnitQuaternion<f32>,
+        scale: Vector3<f32>,
+    ) -> Self {
+        let mut transform = Self {
+            translation,
+            rotation,
+            scale,
+            world_matrix: Matrix4::identity(),
+        };
+        transform.world_matrix = transform.local_matrix();
+        transform
+    }
+
+    pub fn from_translation(translation: Vector3<f32>) -> Self {
+        Self::new(
+            translation,
+            UnitQuaternion::identity(),
+            Vector3::repeat(1.0),
+        )
+    }
+
+    /// Scale, then rotate, then translate
+    pub fn local_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+
+    /// World matrix as of the last propagation pass
+    pub fn world_matrix(&self) -> Matrix4<f32> {
+        self.world_matrix
+    }
+
+    pub(crate) fn set_world_matrix(&mut self, matrix: Matrix4<f32>) {
+        self.world_matrix = matrix;
+    }
+}
+
+impl Default for TransformComponent {
+    fn default() -> Self {
+        Self::from_translation(Vector3::zeros())
+    }
+}
+
+impl Component for RenderComponent {
+    fn snapshot(&self) -> Option<Self> {
+        Some(RenderComponent {})
+    }
+}
+
+impl Component for Name {
+    fn snapshot(&self) -> Option<Self> {
+        Some(Name(self.0.clone()))
+    }
+}
+
+impl Component for TransformComponent {
+    fn snapshot(&self) -> Option<Self> {
+        Some(self.clone())
+    }
+}
+
+impl ComponentsData {
+    pub fn new() -> Self {
+        let mut components = Self {
+            columns: HashMap::new(),
+            tables: Archetypes::new(),
+            locks: HashMap::new(),
+            change_tick: AtomicUsize::new(1),
+            removed: HashMap::new(),
+        };
+
+        components.register::<RenderComponent>();
+        components.register::<TransformComponent>();
+        components
+    }
+
+    /// Creates the column for `T`. Registering the same type twice is a no-op.
+    /// Table components get their columns when the first archetype holding them is created
+    pub fn register<T: Component>(&mut self) {
+        self.locks
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| (RwLock::default(), type_name::<T>()));
+
+        if T::STORAGE == StorageType::Table {
+            return;
+        }
+
+        self.columns
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Column::<T>::new()));
+    }
+
+    /// Safe without the lock of `T`, only structural changes add or remove components
+    pub fn contains<T: Component>(&self, entity: EntityID) -> bool {
+        match T::STORAGE {
+            StorageType::Sparse => self
+                .column::<T>()
+                .is_some_and(|column| column.contains(entity.id)),
+            StorageType::Table => self.tables.location(entity.id).is_some_and(|location| {
+                self.tables.archetypes()[location.archetype].contains::<T>()
+            }),
+        }
+    }
+
+    pub fn is_registered<T: Component>(&self) -> bool {
+        match T::STORAGE {
+            StorageType::Sparse => self.columns.contains_key(&TypeId::of::<T>()),
+            StorageType::Table => self.tables.has_column::<T>(),
+        }
+    }
+
+    /// Places a new entity in the empty archetype so queries can find it
+    pub(crate) fn spawn(&mut self, entity: EntityID) {
+        self.tables.spawn(entity);
+    }
+
+    /// Places the new entities straight in the archetype of `B`, instead of moving them once per component
+    pub(crate) fn spawn_bundles<B: Bundle>(&mut self, entities: &[EntityID], bundles: Vec<B>) {
+        B::register(self);
+
+        let mut columns = HashMap::new();
+        B::table_columns(&mut columns);
+        let archetype = self.tables.archetype_with(columns);
+        self.tables.reserve(archetype, entities.len());
+
+        let tick = self.change_tick();
+        for (entity, bundle) in entities.iter().zip(bundles) {
+            self.tables.spawn_in(*entity, archetype);
+            bundle.write(self, *entity, archetype, tick);
+        }
+    }
+
+    /// Part of `Bundle::write`, the entity's row was just pushed onto `archetype`
+    pub(crate) fn write_spawned<T: Component>(
+        &mut self,
+        entity: EntityID,
+        archetype: ArchetypeId,
+        component: T,
+        tick: usize,
+    ) {
+        match T::STORAGE {
+            StorageType::Sparse => {
+                self.column_mut::<T>()
+                    .unwrap()
+                    .insert(entity.id, component, tick);
+            }
+            StorageType::Table => self
+                .tables
+                .archetype_mut(archetype)
+                .column_mut::<T>()
+                .unwrap()
+                .push(component, ComponentTicks::new(tick)),
+        }
+    }
+
+    /// Registers `T` on first use. Returns the component previously stored for the entity
+    pub fn insert<T: Component>(&mut self, entity: EntityID, component: T) -> Option<T> {
+        self.register::<T>();
+
+        let tick = self.change_tick();
+        match T::STORAGE {
+            StorageType::Sparse => self
+                .column_mut::<T>()
+                .unwrap()
+                .insert(entity.id, component, tick),
+            StorageType::Table => self.tables.insert(entity, component, tick),
+        }
+    }
+
+    /// Blocks while another thread holds `T` for writing
+    pub fn get<T: Component>(&self, entity: EntityID) -> Option<ComponentRef<'_, T>> {
+        let guard = self.lock(TypeId::of::<T>(), false)?;
+        let value = match T::STORAGE {
+            StorageType::Sparse => unsafe { self.column::<T>()?.get(entity.id) },
+            StorageType::Table => unsafe { self.tables.get(entity.id) },
+        }?;
+
+        Some(ComponentRef {
+            _guard: guard,
+            value,
+        })
+    }
+
+    /// Blocks while another thread holds `T`. Writing through it marks the component changed
+    pub fn get_mut<T: Component>(&self, entity: EntityID) -> Option<ComponentMut<'_, T>> {
+        let guard = self.lock(TypeId::of::<T>(), true)?;
+        let (value, component_ticks) = match T::STORAGE {
+            StorageType::Sparse => unsafe { self.column::<T>()?.get_unchecked_mut(entity.id) },
+            StorageType::Table => unsafe { self.tables.get_unchecked_mut(entity.id) },
+        }?;
+
+        Some(ComponentMut {
+            _guard: guard,
+            value: Mut::new(value, component_ticks, SystemTicks::current(self)),
+        })
+    }
+
+    /// None if the component type was never registered, there is nothing to guard then.
+    /// Panics if the thread already holds the lock, waiting on it would never end
+    pub(crate) fn lock(&self, ty: TypeId, write: bool) -> Option<ComponentGuard<'_>> {
+        let (lock, name) = self.locks.get(&ty)?;
+        let held = HeldLock::new(self as *const Self as usize, ty, name);
+
+        let guard = match write {
+            true => LockGuard::Write(lock.write().unwrap_or_else(PoisonError::into_inner)),
+            false => LockGuard::Read(lock.read().unwrap_or_else(PoisonError::into_inner)),
+        };
+        Some(ComponentGuard {
+            _guard: guard,
+            _held: held,
+        })
+    }
+
+    pub fn remove<T: Component>(&mut self, entity: EntityID) -> Option<T> {
+        let component = match T::STORAGE {
+            StorageType::Sparse => self.column_mut::<T>()?.remove(entity.id),
+            StorageType::Table => self.tables.remove(entity.id),
+        }?;
+
+        self.log_removed(TypeId::of::<T>(), entity);
+        Some(component)
+    }
+
+    /// Clears the slot in every column and the entity's table row. Used when an entity is destroyed
+    pub(crate) fn remove_entity(&mut self, entity: EntityID) {
+        let mut removed = self
+            .columns
+            .iter_mut()
+            .filter_map(|(ty, column)| column.remove_entity(entity.id).then_some(*ty))
+            .collect::<Vec<_>>();
+        removed.extend(self.tables.despawn(entity.id));
+
+        for ty in removed {
+            self.log_removed(ty, entity);
+        }
+    }
+
+    /// Moves every component of the entity to `moved` in another world.
+    /// They are logged as removed here and stamped as added there
+    pub(crate) fn move_entity(
+        &mut self,
+        entity: EntityID,
+        target: &mut ComponentsData,
+        moved: EntityID,
+    ) {
+        let mut types = self
+            .columns
+            .iter_mut()
+            .filter_map(|(ty, column)| column.move_to(entity.id, target, moved).then_some(*ty))
+            .collect::<Vec<_>>();
+        types.extend(self.tables.move_entity_to(entity.id, target, moved));
+
+        for ty in types {
+            self.log_removed(ty, entity);
+        }
+    }
+
+    fn log_removed(&mut self, ty: TypeId, entity: EntityID) {
+        let tick = self.change_tick();
+        self.removed.entry(ty).or_default().push((entity, tick));
+    }
+
+    /// Removals of `T` the running system hasn't seen yet
+    pub(crate) fn removed<T: Component>(&self) -> RemovedComponents<'_, T> {
+        let removed = self
+            .removed
+            .get(&TypeId::of::<T>())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        RemovedComponents::new(removed, SystemTicks::current(self).last_run)
+    }
+
+    /// Drops the removals logged before `tick`, once every system has seen them
+    pub(crate) fn clear_removed_before(&mut self, tick: usize) {
+        for removed in self.removed.values_mut() {
+            removed.retain(|(_, removed_at)| *removed_at >= tick);
+        }
+    }
+
+    pub fn change_tick(&self) -> usize {
+        self.change_tick.load(Ordering::Acquire)
+    }
+
+    /// Deep copy of every component that opts into snapshots, with its change ticks.
+    /// Blocks until no system is writing any component type
+    pub(crate) fn snapshot(&self) -> ComponentsData {
+        let mut types = self.locks.keys().copied().collect::<Vec<_>>();
+        types.sort();
+        let _guards = types
+            .iter()
+            .filter_map(|ty| self.lock(*ty, false))
+            .collect::<Vec<_>>();
+
+        //Safety: the read lock of every type is held
+        let (columns, tables) = unsafe {
+            let columns = self
+                .columns
+                .iter()
+                .filter_map(|(ty, column)| Some((*ty, column.snapshot()?)))
+                .collect();
+            (columns, self.tables.snapshot())
+        };
+
+        ComponentsData {
+            columns,
+            tables,
+            locks: self
+                .locks
+                .iter()
+                .map(|(ty, (_, name))| (*ty, (RwLock::default(), *name)))
+                .collect(),
+            change_tick: AtomicUsize::new(self.change_tick()),
+            removed: self.removed.clone(),
+        }
+    }
+
+    /// Every component of the entity with its change ticks, sparse ones first.
+    /// Only used on snapshots, which nothing else can lock
+    pub(crate) fn component_ticks(
+        &self,
+        entity: EntityID,
+    ) -> Vec<(TypeId, &'static str, ComponentTicks)> {
+        //Safety: snapshots are only reachable through `&Snapshot`, no system can write to them
+        unsafe {
+            let mut components = self
+                .columns
+                .iter()
+                .filter_map(|(ty, column)| {
+                    Some((*ty, column.component_name(), column.ticks_of(entity.id)?))
+                })
+                .collect::<Vec<_>>();
+            components.extend(self.tables.component_ticks(entity.id));
+            components
+        }
+    }
+
+    pub(crate) fn set_change_tick(&mut self, tick: usize) {
+        *self.change_tick.get_mut() = tick;
+    }
+
+    /// Starts a new system run and returns its tick
+    pub(crate) fn increment_change_tick(&self) -> usize {
+        self.change_tick.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    pub(crate) fn column<T: Component>(&self) -> Option<&Column<T>> {
+        self.columns
+            .get(&TypeId::of::<T>())
+            .and_then(|column| column.as_any().downcast_ref::<Column<T>>())
+    }
+
+    pub(crate) fn column_mut<T: Component>(&mut self) -> Option<&mut Column<T>> {
+        self.columns
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|column| column.as_any_mut().downcast_mut::<Column<T>>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Component, StorageType};
+    use crate::core::engine::EntityManager;
+
+    #[derive(Debug, PartialEq)]
+    struct Speed(i32);
+    #[derive(Debug, PartialEq)]
+    struct Position(i32);
+
+    impl Component for Speed {}
+
+    impl Component for Position {
+        const STORAGE: StorageType = StorageType::Table;
+    }
+
+    crate::tags!(Player, Enemy);
+
+    fn world() -> EntityManager {
+        let mut entities = EntityManager::new();
+        let entity = entities.create_entity();
+        entities.insert_component(entity, Speed(1)).unwrap();
+        entities.insert_component(entity, Position(2)).unwrap();
+        entities
+    }
+
+    #[test]
+    #[should_panic(expected = "Speed is already locked by this thread")]
+    fn borrowing_a_queried_component_panics() {
+        let entities = world();
+        let entity = entities.entities().next().unwrap();
+
+        let _query = entities.query::<&mut Speed>();
+        let _ = entities.get_component::<Speed>(entity);
+    }
+
+    #[test]
+    #[should_panic(expected = "Position is already locked by this thread")]
+    fn second_query_on_a_held_component_panics() {
+        let entities = world();
+
+        let _positions = entities.query::<&Position>();
+        let _ = entities.query::<(&Speed, &mut Position)>();
+    }
+
+    #[test]
+    fn locks_are_released_with_their_borrows() {
+        let entities = world();
+        let entity = entities.entities().next().unwrap();
+
+        {
+            let mut query = entities.query::<&mut Speed>();
+            for mut speed in query.iter() {
+                speed.0 += 1;
+            }
+        }
+        let speed = entities.get_component::<Speed>(entity).unwrap();
+        //Other component types and other worlds are separate locks
+        let position = entities.get_component_mut::<Position>(entity).unwrap();
+        let other = world();
+        assert_eq!(other.query::<&Speed>().iter().count(), 1);
+        assert_eq!((speed.0, position.0), (2, 2));
+    }
+
+    #[test]
+    fn tags_are_table_components() {
+        let mut entities = world();
+        let player = entities.create_entity();
+        entities.insert_component(player, Player).unwrap();
+        let enemy = entities.spawn_bundle((Enemy, Speed(3))).unwrap();
+
+        assert!(entities.has_component::<Player>(player));
+        assert!(!entities.has_component::<Enemy>(player));
+        assert!(entities.has_component::<Enemy>(enemy));
+        assert_eq!(entities.query::<&Player>().iter().count(), 1);
+        assert_eq!(Player::STORAGE, StorageType::Table);
+        assert_eq!(Enemy.snapshot(), Some(Enemy));
+
+        entities.remove_component::<Player>(player);
+        assert!(!entities.has_component::<Player>(player));
+    }
+}
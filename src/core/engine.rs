@@ -1,114 +1,842 @@
 use glfw::WindowEvent;
-use itertools::Itertools;
 use std::{
-    collections::LinkedList,
-    sync::{Arc, RwLock},
-    thread::{self, yield_now, JoinHandle},
-    time::Duration,
+    any::{Any, TypeId},
+    collections::{BTreeMap, BTreeSet, HashMap, LinkedList},
+    panic::{catch_unwind, AssertUnwindSafe},
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError, RwLock,
+    },
+    thread::{self, yield_now},
+    time::{Duration, Instant},
     usize, vec,
 };
 
-use super::{camera::Camera, components::SampleComponent, system::System};
-use crossbeam_channel::{bounded, Receiver, Sender};
+use super::{
+    bundle::{check_duplicates, Bundle},
+    camera::Camera,
+    change_detection::{run_system, RemovedComponents},
+    commands::Commands,
+    components::{Component, ComponentMut, ComponentRef, ComponentsData, Name},
+    events::{Event, EventWriter, Events},
+    hierarchy::propagate_transforms,
+    input::Input,
+    paged_vec::PagedVec,
+    prefab::{Prefab, Prefabs},
+    query::{Query, QueryFilter, WorldQuery},
+    relations::{Relation, Relations},
+    resources::{Res, ResMut, Resource, Resources},
+    schedule::{Schedule, ScheduleError, Stage},
+    snapshot::Snapshot,
+    system::{find_conflicts, AccessConflict, IntoSystem, System},
+    task_pool::{Scope, TaskPool},
+    time::{Clock, FramePacing, FrameStats, Time},
+};
+use crossbeam_channel::{unbounded, Sender};
 use sysinfo::{System as HardWareSystem, SystemExt};
 
-pub const TOTAL_ENTITIES: usize = 1_000;
-
-#[derive(Debug, Clone)]
-pub enum GameStateEvent {
-    InputEvent(WindowEvent),
-}
-
 #[derive(Debug, Clone)]
 pub enum SystemEvent {
     ShutdownEngine,
-    EngineEvent(GameStateEvent),
     AssetSystemEvent,
 }
 
-struct Worker {
-    _join_handle: JoinHandle<()>,
+/// A system with what it carries over from one frame to the next
+struct SystemSlot {
+    world: WorldId,
+    system: System,
+    /// Change tick of its previous step
+    last_run: usize,
+    commands: Commands,
+    events: Vec<SystemEvent>,
 }
 
-/**
- * @game_state_channel -> Each worker thread will subscribe to this to game state changes
- * @system_events_channel -> Events received from the systems. Events will be broadcasted to all the systems
- * @game_tick_channel -> Send tick event together with the time that passes
- *
- */
+/// Owns the systems of a stage and runs them on the task pool, in schedule order
 struct SystemManager {
-    tick_broadcast_bus: bus::Bus<usize>,
-    game_state_broadcast_bus: bus::Bus<Vec<GameStateEvent>>,
-    system_events_channel: (Sender<Vec<SystemEvent>>, Receiver<Vec<SystemEvent>>),
-    thread_count: usize,
-    no_of_systems: Option<usize>,
-    workers: Vec<Worker>,
+    systems: Vec<Mutex<SystemSlot>>,
+    schedule: Schedule,
+    /// Systems waiting on each system
+    dependents: Vec<Vec<usize>>,
+    main_thread: Vec<bool>,
+    access_conflicts: Vec<AccessConflict>,
 }
 
 impl SystemManager {
-    fn new(thread_count: usize) -> Self {
+    fn new(systems: Vec<(WorldId, System)>, schedule: Schedule) -> Self {
+        let mut dependents = vec![vec![]; systems.len()];
+        for system in 0..systems.len() {
+            for dependency in schedule.dependencies(system) {
+                dependents[*dependency].push(system);
+            }
+        }
+
+        let access_conflicts = find_conflicts(&systems, &schedule);
+        let main_thread = systems
+            .iter()
+            .map(|(_, system)| system.main_thread())
+            .collect();
+
+        let systems = systems
+            .into_iter()
+            .map(|(world, system)| {
+                Mutex::new(SystemSlot {
+                    world,
+                    system,
+                    last_run: 0,
+                    commands: Commands::new(),
+                    events: vec![],
+                })
+            })
+            .collect();
+
         Self {
-            tick_broadcast_bus: bus::Bus::new(1),
-            game_state_broadcast_bus: bus::Bus::new(1),
-            system_events_channel: bounded(10),
-            thread_count,
-            no_of_systems: None,
-            workers: vec![],
+            systems,
+            schedule,
+            dependents,
+            main_thread,
+            access_conflicts,
+        }
+    }
+
+    fn step(&self, system: usize, time: usize, engine: &Engine) {
+        let mut slot = self.systems[system]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let SystemSlot {
+            world,
+            system,
+            last_run,
+            commands,
+            events,
+        } = &mut *slot;
+
+        let entities = engine.world(*world).unwrap();
+        let mut this_run = None;
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let tick = entities
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .increment_change_tick();
+            this_run = Some(tick);
+            run_system(*last_run, || {
+                system.update(time, entities, commands, engine)
+            })
+        }));
+        if let Some(this_run) = this_run {
+            *last_run = this_run;
+        }
+
+        match result {
+            Ok(Ok(new_events)) => events.extend(new_events),
+            Ok(Err(error)) => eprintln!("{} failed its step: {:?}", system.name(), error),
+            Err(_) => {
+                eprintln!("{} panicked during its step", system.name());
+                //A write guard held across the panic poisons the world, the others go on with it
+                entities.clear_poison();
+            }
+        }
+    }
+
+    /// Change tick the least recently run system of the world started its previous step at
+    fn oldest_run(&self, world: WorldId) -> Option<usize> {
+        self.systems
+            .iter()
+            .map(|slot| slot.lock().unwrap_or_else(PoisonError::into_inner))
+            .filter(|slot| slot.world == world)
+            .map(|slot| slot.last_run)
+            .min()
+    }
+
+    /// Calls the shutdown hook of every system in schedule order.
+    /// Returns the names of the systems that panicked in it
+    fn shutdown(&self) -> Vec<String> {
+        let mut panicked = vec![];
+
+        for system in self.schedule.order().iter().copied() {
+            let mut slot = self.systems[system]
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if catch_unwind(AssertUnwindSafe(|| slot.system.shutdown())).is_err() {
+                eprintln!("{} panicked during its shutdown", slot.system.name());
+                panicked.push(slot.system.name());
+            }
+        }
+
+        panicked
+    }
+
+    /// Events and commands of the step, in schedule order.
+    /// Only called once every system is done, nothing is left behind for the next frame
+    fn drain(&self) -> (Vec<SystemEvent>, Vec<(WorldId, Commands)>) {
+        let mut events = vec![];
+        let mut commands = vec![];
+
+        for system in self.schedule.order().iter().copied() {
+            let mut slot = self.systems[system]
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            events.append(&mut slot.events);
+            commands.push((slot.world, std::mem::take(&mut slot.commands)));
+        }
+
+        (events, commands)
+    }
+}
+
+/// Progress of the systems during one step of a stage
+struct Frame<'a> {
+    engine: &'a Engine,
+    systems_manager: &'a SystemManager,
+    time: usize,
+    /// Dependencies each system is still waiting on
+    waiting: Vec<AtomicUsize>,
+    finished: AtomicUsize,
+    main_thread: Sender<usize>,
+}
+
+impl<'a> Frame<'a> {
+    fn dispatch(&'a self, scope: &Scope<'a>, system: usize) {
+        match self.systems_manager.main_thread[system] {
+            true => self.main_thread.send(system).unwrap(),
+            false => scope.spawn(move |scope| self.run(scope, system)),
+        }
+    }
+
+    fn run(&'a self, scope: &Scope<'a>, system: usize) {
+        let _done = SystemDone {
+            frame: self,
+            scope,
+            system,
+        };
+        self.systems_manager.step(system, self.time, self.engine);
+    }
+}
+
+/// Releases the dependents of a system and counts it as finished once its step is over.
+/// Runs on unwind too, the frame would wait on the system forever otherwise
+struct SystemDone<'f, 'a> {
+    frame: &'a Frame<'a>,
+    scope: &'f Scope<'a>,
+    system: usize,
+}
+
+impl Drop for SystemDone<'_, '_> {
+    fn drop(&mut self) {
+        for dependent in self.frame.systems_manager.dependents[self.system]
+            .iter()
+            .copied()
+        {
+            if self.frame.waiting[dependent].fetch_sub(1, Ordering::AcqRel) == 1 {
+                self.frame.dispatch(self.scope, dependent);
+            }
         }
+        self.frame.finished.fetch_add(1, Ordering::AcqRel);
     }
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
-struct EntityID {
-    id: usize,
-    gen: usize,
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Hash)]
+pub struct EntityID {
+    pub(crate) id: usize,
+    pub(crate) gen: usize,
 }
 
-impl EntityID {}
+impl EntityID {
+    /// Slot of the entity. Slots are reused once an entity is destroyed
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Bumped every time the slot is reused so that old handles can be told apart
+    pub fn gen(&self) -> usize {
+        self.gen
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntityError {
+    /// The entity was destroyed or the handle belongs to an older generation of the slot
+    DeadEntity(EntityID),
+    /// Creating the entity would go past the limit set through `EngineBuilder::entity_limit`
+    LimitReached(usize),
+    /// A command targeted an entity spawned earlier in the same buffer, but that spawn failed
+    UnresolvedSpawn(usize),
+    /// Parenting the entity would make it its own ancestor
+    HierarchyCycle(EntityID),
+    /// The bundle holds this component type more than once
+    DuplicateComponent(&'static str),
+    /// The name already belongs to another entity, which is returned
+    NameTaken(EntityID),
+    /// The engine has no world with this id, see `EngineBuilder::add_world`
+    UnknownWorld(WorldId),
+}
+
+/// Identifies one of the engine's worlds. Worlds don't share entities, components or resources
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Hash)]
+pub struct WorldId(pub usize);
+
+impl WorldId {
+    /// World of `EngineBuilder::add_system` and of the level manager
+    pub const MAIN: WorldId = WorldId(0);
+}
+
+/// Swaps the buffers of one event type, see `EntityManager::add_event`
+type EventUpdate = fn(&EntityManager);
 
 pub struct EntityManager {
     deleted_entities: LinkedList<EntityID>,
-    entities: Vec<EntityID>,
+    entities: PagedVec<EntityID>,
+    alive: PagedVec<bool>,
     components: ComponentsData,
+    entity_limit: Option<usize>,
+    names: HashMap<String, EntityID>,
+    resources: Resources,
+    relations: Relations,
+    /// Swaps the buffers of each event type added with `add_event`
+    event_updates: Vec<(TypeId, EventUpdate)>,
 }
 
-///We have to be careful to avoid any realocations withing the entities and render components
+///Entities and sparse components are paged so growing the pools never moves what is already there
 impl EntityManager {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
+        Self::with_entity_limit(None)
+    }
+
+    pub(crate) fn with_entity_limit(entity_limit: Option<usize>) -> Self {
         Self {
             deleted_entities: LinkedList::new(),
-            entities: Vec::with_capacity(TOTAL_ENTITIES),
+            entities: PagedVec::new(),
+            alive: PagedVec::new(),
             components: ComponentsData::new(),
+            entity_limit,
+            names: HashMap::new(),
+            resources: Resources::new(),
+            relations: Relations::new(),
+            event_updates: vec![],
         }
     }
 
-    fn create_entity(&mut self) -> EntityID {
-        if self.deleted_entities.len() > 0 {
-            let new_entity = self.deleted_entities.pop_front().unwrap();
-            self.entities[new_entity.id].gen += 1;
-            return new_entity;
+    /// Panics when the entity limit is reached, see `try_create_entity`
+    pub fn create_entity(&mut self) -> EntityID {
+        self.try_create_entity().expect("Entity limit reached")
+    }
+
+    pub fn try_create_entity(&mut self) -> Result<EntityID, EntityError> {
+        let new_entity = self.allocate_entities(1)?[0];
+        self.components.spawn(new_entity);
+        Ok(new_entity)
+    }
+
+    /// Spawns an entity with every component of the bundle
+    /// ```ignore
+    /// let entity = entities.spawn_bundle((TransformComponent::default(), RenderComponent {}))?;
+    /// ```
+    pub fn spawn_bundle<B: Bundle>(&mut self, bundle: B) -> Result<EntityID, EntityError> {
+        Ok(self.spawn_batch([bundle])?[0])
+    }
+
+    /// Spawns one entity per bundle. Slots and table rows are reserved up front
+    /// and nothing is spawned if the batch would go past the entity limit,
+    /// if a `Name` in it is already taken or if the bundle holds a component type twice
+    pub fn spawn_batch<B: Bundle>(
+        &mut self,
+        bundles: impl IntoIterator<Item = B>,
+    ) -> Result<Vec<EntityID>, EntityError> {
+        check_duplicates::<B>()?;
+        let bundles = bundles.into_iter().collect::<Vec<_>>();
+        let entities = self.allocate_entities(bundles.len())?;
+
+        if !B::component_types()
+            .iter()
+            .any(|(ty, _)| *ty == TypeId::of::<Name>())
+        {
+            self.components.spawn_bundles(&entities, bundles);
+            return Ok(entities);
         }
 
-        let new_entity = EntityID {
-            id: self.entities.len(),
-            gen: 1,
-        };
-        self.entities.push(new_entity);
+        //Names go through `insert_component` to keep the name index in sync,
+        //a name clash destroys the whole batch
+        for entity in &entities {
+            self.components.spawn(*entity);
+        }
+        for (entity, bundle) in entities.iter().zip(bundles) {
+            if let Err(error) = bundle.insert(self, *entity) {
+                for entity in &entities {
+                    self.destroy_entity(*entity);
+                }
+                return Err(error);
+            }
+        }
+
+        Ok(entities)
+    }
+
+    /// Adds the components of the bundle to an existing entity, replacing the ones it already has.
+    /// Nothing is inserted if the bundle holds a component type twice
+    pub fn insert_bundle<B: Bundle>(
+        &mut self,
+        entity: EntityID,
+        bundle: B,
+    ) -> Result<(), EntityError> {
+        if !self.is_alive(entity) {
+            return Err(EntityError::DeadEntity(entity));
+        }
+
+        check_duplicates::<B>()?;
+        bundle.insert(self, entity)
+    }
+
+    /// Takes `count` slots, reusing deleted ones first.
+    /// The entities still have to be placed in the component tables
+    fn allocate_entities(&mut self, count: usize) -> Result<Vec<EntityID>, EntityError> {
+        let reused = count.min(self.deleted_entities.len());
+
+        if let Some(limit) = self.entity_limit {
+            if self.entities.len() + (count - reused) > limit {
+                return Err(EntityError::LimitReached(limit));
+            }
+        }
+
+        let remaining = self.deleted_entities.split_off(reused);
+        let deleted = std::mem::replace(&mut self.deleted_entities, remaining);
+
+        let mut new_entities = Vec::with_capacity(count);
+        for deleted_entity in deleted {
+            let new_entity = &mut self.entities[deleted_entity.id];
+            new_entity.gen += 1;
+            self.alive[new_entity.id] = true;
+            new_entities.push(*new_entity);
+        }
+
+        while new_entities.len() < count {
+            let new_entity = EntityID {
+                id: self.entities.len(),
+                gen: 1,
+            };
+            self.entities.push(new_entity);
+            self.alive.push(true);
+            new_entities.push(new_entity);
+        }
+
+        Ok(new_entities)
+    }
+
+    /// Drops all the components of the entity and frees its slot for reuse.
+    /// Its children are kept alive as roots, see `destroy_entity_recursive`.
+    /// Returns false if the handle is stale
+    pub fn destroy_entity(&mut self, entity: EntityID) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+
+        self.detach_hierarchy(entity);
+        self.remove_name(entity);
+        self.relations.remove_entity(entity);
+        self.alive[entity.id] = false;
+        self.components.remove_entity(entity);
+        self.deleted_entities.push_back(entity);
+        true
+    }
+
+    /// Every live entity, in slot order
+    pub fn entities(&self) -> impl Iterator<Item = EntityID> + '_ {
+        (0..self.entities.len())
+            .filter(|id| self.alive[*id])
+            .map(|id| self.entities[id])
+    }
+
+    pub fn is_alive(&self, entity: EntityID) -> bool {
+        match self.entities.get(entity.id) {
+            Some(current) => current.gen == entity.gen && self.alive[entity.id],
+            None => false,
+        }
+    }
+
+    /// Returns the component the entity previously had
+    pub fn insert_component<T: Component>(
+        &mut self,
+        entity: EntityID,
+        component: T,
+    ) -> Result<Option<T>, EntityError> {
+        if !self.is_alive(entity) {
+            return Err(EntityError::DeadEntity(entity));
+        }
+
+        //Keep the name index in sync however the name got inserted
+        let name = (&component as &dyn Any)
+            .downcast_ref::<Name>()
+            .map(|name| name.as_str().to_string());
+        if let Some(owner) = name.as_deref().and_then(|name| self.find_by_name(name)) {
+            if owner != entity {
+                return Err(EntityError::NameTaken(owner));
+            }
+        }
+
+        let previous = self.components.insert(entity, component);
+        if let Some(name) = name {
+            if let Some(previous) = previous
+                .as_ref()
+                .and_then(|previous| (previous as &dyn Any).downcast_ref::<Name>())
+            {
+                self.names.remove(previous.as_str());
+            }
+            self.names.insert(name, entity);
+        }
+
+        Ok(previous)
+    }
+
+    /// Read locks `T` for as long as the reference is held.
+    /// Panics if the thread already holds `T` through a query or another reference
+    pub fn get_component<T: Component>(&self, entity: EntityID) -> Option<ComponentRef<'_, T>> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+
+        self.components.get(entity)
+    }
+
+    /// Write locks `T` for as long as the reference is held, with the same rule as `get_component`.
+    /// Only needs a shared `EntityManager` so it works behind the `EntityManagerRef` read lock
+    pub fn get_component_mut<T: Component>(&self, entity: EntityID) -> Option<ComponentMut<'_, T>> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+
+        self.components.get_mut(entity)
+    }
 
-        return new_entity;
+    pub fn remove_component<T: Component>(&mut self, entity: EntityID) -> Option<T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+
+        let component = self.components.remove(entity)?;
+
+        //Keep the name index in sync however the name got removed
+        if let Some(name) = (&component as &dyn Any).downcast_ref::<Name>() {
+            self.names.remove(name.as_str());
+        }
+
+        Some(component)
+    }
+
+    /// Borrows every live entity that has all the components in `Q`.
+    /// Locks the component types in `Q`, so systems touching other components keep running.
+    /// Borrowing a type the thread already holds through another query or component reference
+    /// panics. Across threads the locks are only ordered within one query, see `SystemTrait::access`
+    /// ```ignore
+    /// let entities = entities.read().unwrap();
+    /// let mut query = entities.query::<(&TransformComponent, &mut RenderComponent)>();
+    /// for (transform, render) in query.iter() {}
+    /// ```
+    pub fn query<Q: WorldQuery>(&self) -> Query<'_, Q> {
+        Query::new(&self.components)
+    }
+
+    /// Same as `query` but entities must also pass `F`, e.g. `Without<T>`
+    pub fn query_filtered<Q: WorldQuery, F: QueryFilter>(&self) -> Query<'_, Q, F> {
+        Query::new(&self.components)
+    }
+
+    /// Entities that lost `T` since the calling system last ran
+    /// ```ignore
+    /// for entity in entities.removed::<RenderComponent>() {
+    ///     //Free the entity's buffers
+    /// }
+    /// ```
+    pub fn removed<T: Component>(&self) -> RemovedComponents<'_, T> {
+        self.components.removed::<T>()
+    }
+
+    pub fn change_tick(&self) -> usize {
+        self.components.change_tick()
+    }
+
+    pub(crate) fn increment_change_tick(&self) -> usize {
+        self.components.increment_change_tick()
+    }
+
+    /// Checks for the component without taking its lock. Cheap enough for tag components
+    pub fn has_component<T: Component>(&self, entity: EntityID) -> bool {
+        self.is_alive(entity) && self.components.contains::<T>(entity)
+    }
+
+    /// Names are unique, naming an entity again replaces its previous name
+    pub fn set_name(
+        &mut self,
+        entity: EntityID,
+        name: impl Into<String>,
+    ) -> Result<(), EntityError> {
+        self.insert_component(entity, Name(name.into())).map(|_| ())
+    }
+
+    /// Returns the name the entity had
+    pub fn remove_name(&mut self, entity: EntityID) -> Option<String> {
+        self.remove_component::<Name>(entity)
+            .map(|name| name.as_str().to_string())
+    }
+
+    pub fn name(&self, entity: EntityID) -> Option<String> {
+        self.get_component::<Name>(entity)
+            .map(|name| name.as_str().to_string())
+    }
+
+    pub fn find_by_name(&self, name: &str) -> Option<EntityID> {
+        self.names.get(name).copied()
+    }
+
+    /// Registers the prefab under its name in the `Prefabs` resource. Returns the prefab it replaced
+    pub fn add_prefab(&mut self, prefab: Arc<Prefab>) -> Option<Arc<Prefab>> {
+        if !self.has_resource::<Prefabs>() {
+            self.insert_resource(Prefabs::new());
+        }
+
+        self.resource_mut::<Prefabs>().unwrap().insert(prefab)
+    }
+
+    /// Locks the `Prefabs` resource for the lookup
+    pub fn prefab(&self, name: &str) -> Option<Arc<Prefab>> {
+        self.resource::<Prefabs>()?.get(name)
+    }
+
+    /// Returns the resource it replaced
+    pub fn insert_resource<T: Resource>(&mut self, resource: T) -> Option<T> {
+        self.resources.insert(resource)
+    }
+
+    pub fn remove_resource<T: Resource>(&mut self) -> Option<T> {
+        self.resources.remove()
+    }
+
+    pub fn has_resource<T: Resource>(&self) -> bool {
+        self.resources.contains::<T>()
+    }
+
+    /// Locks the resource like a component, borrowing it again while holding it panics.
+    /// Declare it with `Access::read_resource`
+    /// ```ignore
+    /// let entities = entities.read().unwrap();
+    /// let camera = entities.resource::<Camera>().unwrap();
+    /// ```
+    pub fn resource<T: Resource>(&self) -> Option<Res<'_, T>> {
+        self.resources.get()
+    }
+
+    /// Declare it with `Access::write_resource`
+    pub fn resource_mut<T: Resource>(&self) -> Option<ResMut<'_, T>> {
+        self.resources.get_mut()
+    }
+
+    /// Creates the `Events<T>` resource. Adding the same type twice does nothing
+    pub fn add_event<T: Event>(&mut self) {
+        if self
+            .event_updates
+            .iter()
+            .any(|(ty, _)| *ty == TypeId::of::<T>())
+        {
+            return;
+        }
+
+        self.insert_resource(Events::<T>::new());
+        self.event_updates.push((TypeId::of::<T>(), |entities| {
+            if let Some(mut events) = entities.resource_mut::<Events<T>>() {
+                events.update();
+            }
+        }));
+    }
+
+    /// Returns false if `T` was not added with `add_event`
+    pub fn send_event<T: Event>(&self, event: T) -> bool {
+        match self.event_writer::<T>() {
+            Some(mut writer) => {
+                writer.send(event);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn event_writer<T: Event>(&self) -> Option<EventWriter<'_, T>> {
+        self.resource_mut::<Events<T>>().map(EventWriter::new)
+    }
+
+    /// Read them through an `EventReader` kept by the system
+    pub fn events<T: Event>(&self) -> Option<Res<'_, Events<T>>> {
+        self.resource::<Events<T>>()
+    }
+
+    /// Called by the engine at the end of the frame, see `Events`
+    pub(crate) fn update_events(&self) {
+        for (_, update) in self.event_updates.iter() {
+            update(self);
+        }
+    }
+
+    /// Relates `source` to `target`. Dropped automatically when either of them is destroyed.
+    /// Returns false if the relation already existed
+    pub fn add_relation<R: Relation>(
+        &mut self,
+        source: EntityID,
+        target: EntityID,
+    ) -> Result<bool, EntityError> {
+        for entity in [source, target] {
+            if !self.is_alive(entity) {
+                return Err(EntityError::DeadEntity(entity));
+            }
+        }
+
+        Ok(self.relations.insert::<R>(source, target))
+    }
+
+    pub fn remove_relation<R: Relation>(&mut self, source: EntityID, target: EntityID) -> bool {
+        self.relations.remove::<R>(source, target)
+    }
+
+    pub fn has_relation<R: Relation>(&self, source: EntityID, target: EntityID) -> bool {
+        self.relations.contains::<R>(source, target)
+    }
+
+    /// Entities `source` relates to through `R`
+    pub fn relation_targets<R: Relation>(&self, source: EntityID) -> &[EntityID] {
+        self.relations.targets::<R>(source)
+    }
+
+    /// Entities relating to `target` through `R`
+    pub fn relation_sources<R: Relation>(&self, target: EntityID) -> &[EntityID] {
+        self.relations.sources::<R>(target)
+    }
+
+    pub fn relations(&self) -> &Relations {
+        &self.relations
+    }
+
+    /// Moves the entity and its components into `target`, where it gets a new id.
+    /// Its name moves along, its hierarchy and relations don't since they refer to entities of this world
+    pub fn move_entity_to(
+        &mut self,
+        entity: EntityID,
+        target: &mut EntityManager,
+    ) -> Result<EntityID, EntityError> {
+        if !self.is_alive(entity) {
+            return Err(EntityError::DeadEntity(entity));
+        }
+
+        let name = self.name(entity);
+        if let Some(owner) = name.as_deref().and_then(|name| target.find_by_name(name)) {
+            return Err(EntityError::NameTaken(owner));
+        }
+
+        let moved = target.try_create_entity()?;
+
+        self.detach_hierarchy(entity);
+        self.remove_name(entity);
+        self.relations.remove_entity(entity);
+        self.components
+            .move_entity(entity, &mut target.components, moved);
+        self.destroy_entity(entity);
+
+        if let Some(name) = name {
+            target.set_name(moved, name)?;
+        }
+
+        Ok(moved)
+    }
+
+    /// Deep copy of the world, including the free list so restoring it hands out the same ids again.
+    /// Waits for systems writing components to finish.
+    /// Bumps the change tick so writes made after it show up in `Snapshot::diff`
+    pub fn snapshot(&self) -> Snapshot {
+        let snapshot = Snapshot::new(self.copy());
+        self.increment_change_tick();
+        snapshot
+    }
+
+    /// Copy of the world that opts in to snapshots, without resources
+    pub(crate) fn copy(&self) -> EntityManager {
+        EntityManager {
+            deleted_entities: self.deleted_entities.clone(),
+            entities: self.entities.clone(),
+            alive: self.alive.clone(),
+            components: self.components.snapshot(),
+            entity_limit: self.entity_limit,
+            names: self.names.clone(),
+            resources: Resources::new(),
+            relations: self.relations.clone(),
+            event_updates: vec![],
+        }
+    }
+
+    /// Puts the world back the way it was when the snapshot was taken.
+    /// Resources, `Prefabs` among them, are kept.
+    /// Components keep the ticks they had in the snapshot, the change tick itself never goes back
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let tick = self.change_tick();
+        let world = snapshot.world().copy();
+
+        self.deleted_entities = world.deleted_entities;
+        self.entities = world.entities;
+        self.alive = world.alive;
+        self.components = world.components;
+        self.components
+            .set_change_tick(tick.max(snapshot.change_tick()));
+        self.entity_limit = world.entity_limit;
+        self.names = world.names;
+        self.relations = world.relations;
+    }
+
+    pub(crate) fn components(&self) -> &ComponentsData {
+        &self.components
     }
 }
 
 pub type EntityManagerRef = Arc<RwLock<EntityManager>>;
 
-//TODO: (Teddy) Add detlta time parameter
+#[derive(Clone)]
+struct World {
+    entities: EntityManagerRef,
+    /// Change tick at the start of the previous frame
+    previous_tick: usize,
+}
 
 #[derive(Clone)]
 pub struct Engine {
-    systems_manager: Arc<RwLock<SystemManager>>,
-    previous_tick: usize,
-    entity_manager: EntityManagerRef,
-    pub camera: Arc<RwLock<Camera>>,
+    fixed_systems: Arc<SystemManager>,
+    variable_systems: Arc<SystemManager>,
+    task_pool: Arc<TaskPool>,
+    worlds: BTreeMap<WorldId, World>,
+    clock: Clock,
+    frame_stats: FrameStats,
+    pacing: FramePacing,
+    target_frame_rate: u32,
+    /// See `EngineBuilder::deterministic`
+    deterministic: bool,
+    /// Set once a system sent `SystemEvent::ShutdownEngine`
+    shutdown_requested: bool,
+}
+
+/// How `Engine::shutdown` went
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExitStatus {
+    /// Systems whose shutdown hook panicked
+    pub panicked: Vec<String>,
+    /// Pool threads still busy when the timeout ran out, left running
+    pub detached_threads: usize,
+}
+
+impl ExitStatus {
+    pub fn success(&self) -> bool {
+        self.panicked.is_empty() && self.detached_threads == 0
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        match status.success() {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::FAILURE,
+        }
+    }
 }
 
 /*
@@ -124,34 +852,51 @@ impl Engine {
      *Broadcast to other system
      *Update entit's components
      */
+    /// Runs the fixed stage once for every fixed timestep elapsed since the previous frame,
+    /// then the variable stage once
     pub fn update(&mut self) {
-        let systems_manager = self.systems_manager.clone();
-        let mut systems_manager_lock = systems_manager.write().unwrap();
-
-        systems_manager_lock.tick_broadcast_bus.broadcast(16);
-        systems_manager_lock
-            .game_state_broadcast_bus
-            .broadcast(vec![]);
+        let frame_start = Instant::now();
+        let frame_ticks = self
+            .worlds
+            .values()
+            .map(|world| {
+                world
+                    .entities
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .change_tick()
+            })
+            .collect::<Vec<_>>();
 
+        self.clock.start_frame();
         let mut events = vec![];
+        while self.clock.fixed_step() {
+            events.extend(self.run_stage(&self.fixed_systems));
+        }
+        self.clock.variable_step();
+        events.extend(self.run_stage(&self.variable_systems));
 
-        let mut counter = 0;
-        while let Ok(new_events) = systems_manager_lock
-            .system_events_channel
-            .1
-            .recv_timeout(Duration::from_millis(16))
-        {
-            events.extend(new_events);
-            counter += 1;
+        for ((id, world), frame_tick) in self.worlds.iter_mut().zip(frame_ticks) {
+            //Fixed systems can go several frames without running, keep what they haven't seen
+            let oldest_run = [&self.fixed_systems, &self.variable_systems]
+                .iter()
+                .filter_map(|systems| systems.oldest_run(*id))
+                .min()
+                .unwrap_or(world.previous_tick);
 
-            let events_size = Self::events_channel_size(
-                systems_manager_lock.no_of_systems.unwrap(),
-                systems_manager_lock.thread_count,
-            );
+            let mut entity_manager = world
+                .entities
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+            entity_manager
+                .components
+                .clear_removed_before(world.previous_tick.min(oldest_run));
+            world.previous_tick = frame_tick;
 
-            if counter == events_size {
-                break;
-            }
+            //Every frame, even without a fixed step, so events of skipped frames don't pile up
+            entity_manager.update_events();
+
+            propagate_transforms(&entity_manager);
         }
 
         //dbg!(&events);
@@ -159,145 +904,309 @@ impl Engine {
         for event in events.iter() {
             match event {
                 SystemEvent::ShutdownEngine => {
-                    //Close the engine. Implement a clean up function
-                    panic!("Closed");
+                    //The frame still completes, the caller stops updating and calls `shutdown`
+                    self.shutdown_requested = true;
                 }
-                SystemEvent::EngineEvent(GameStateEvent::InputEvent(e)) => {
-                    self.update_camera_movements(e);
-                }
-                //Check camera movement and update_camera
                 e => continue,
             }
         }
-    }
 
-    pub fn update_camera_movements(&mut self, e: &WindowEvent) {
-        let camera = self.camera.clone();
-        let camera_lock = camera.read().unwrap();
+        self.update_camera_movements();
 
-        let mut new_camera_pos = camera_lock.pos;
+        //Wall clock time would make the state of two deterministic runs differ
+        let update_time = match self.clock.is_simulated() {
+            true => Duration::ZERO,
+            false => frame_start.elapsed(),
+        };
+        self.frame_stats
+            .record(self.clock.time().delta, update_time, events.len());
+        self.set_resource(self.frame_stats);
+    }
+
+    /// Updates until a system asks for the engine to stop, pacing the frames as set with
+    /// `EngineBuilder::frame_pacing`. Call `shutdown` once it returns
+    pub fn run(&mut self) {
+        let frame_budget = Duration::from_secs(1) / self.target_frame_rate;
+        let mut next_frame = Instant::now();
 
-        match e {
-            WindowEvent::Key(glfw::Key::Up, _, _, _) => new_camera_pos += camera_lock.front * 0.1,
-            WindowEvent::Key(glfw::Key::Down, _, _, _) => new_camera_pos -= camera_lock.front * 0.1,
-            WindowEvent::Key(glfw::Key::Left, _, _, _) => {
-                new_camera_pos -= camera_lock.front.cross(&camera_lock.up).normalize() * 0.1;
+        loop {
+            self.update();
+            if self.shutdown_requested {
+                return;
             }
-            WindowEvent::Key(glfw::Key::Right, _, _, _) => {
-                new_camera_pos += camera_lock.front.cross(&camera_lock.up).normalize() * 0.1;
+
+            if self.pacing == FramePacing::FixedSleep {
+                next_frame += frame_budget;
+                let now = Instant::now();
+                match next_frame.checked_duration_since(now) {
+                    Some(left) => thread::sleep(left),
+                    //Running late, the missed frames are not made up for
+                    None => next_frame = now,
+                }
             }
-            _ => (),
         }
-        drop(camera_lock);
+    }
+
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_stats
+    }
 
-        let mut camera_lock = camera.write().unwrap();
+    /// True once a system asked for the engine to stop, see `shutdown`
+    pub fn shutdown_requested(&self) -> bool {
+        self.shutdown_requested
+    }
+
+    /// Calls `SystemTrait::shutdown` on every system, fixed stage first, then stops the
+    /// pool threads. Threads still busy after `timeout` are left running and reported
+    pub fn shutdown(self, timeout: Duration) -> ExitStatus {
+        let mut panicked = self.fixed_systems.shutdown();
+        panicked.extend(self.variable_systems.shutdown());
+
+        ExitStatus {
+            panicked,
+            detached_threads: self.task_pool.shutdown(timeout),
+        }
+    }
+
+    /// Moves the camera of the main world with the arrow key events of its `Input`
+    pub fn update_camera_movements(&self) {
+        let entity_manager = self
+            .entity_manager()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let (Some(input), Some(mut camera_lock)) = (
+            entity_manager.resource::<Input>(),
+            entity_manager.resource_mut::<Camera>(),
+        ) else {
+            return;
+        };
+
+        let mut new_camera_pos = camera_lock.pos;
+
+        for e in input.events() {
+            match e {
+                WindowEvent::Key(glfw::Key::Up, _, _, _) => {
+                    new_camera_pos += camera_lock.front * 0.1
+                }
+                WindowEvent::Key(glfw::Key::Down, _, _, _) => {
+                    new_camera_pos -= camera_lock.front * 0.1
+                }
+                WindowEvent::Key(glfw::Key::Left, _, _, _) => {
+                    new_camera_pos -= camera_lock.front.cross(&camera_lock.up).normalize() * 0.1;
+                }
+                WindowEvent::Key(glfw::Key::Right, _, _, _) => {
+                    new_camera_pos += camera_lock.front.cross(&camera_lock.up).normalize() * 0.1;
+                }
+                _ => (),
+            }
+        }
         camera_lock.pos = new_camera_pos;
     }
 
-    #[inline]
-    fn events_channel_size(no_of_systems: usize, thread_count: usize) -> usize {
-        if no_of_systems > thread_count {
-            no_of_systems / thread_count
-        } else {
-            no_of_systems
+    /// The main world, see `WorldId::MAIN`
+    pub fn entity_manager(&self) -> &EntityManagerRef {
+        &self.worlds[&WorldId::MAIN].entities
+    }
+
+    pub fn world(&self, world: WorldId) -> Option<&EntityManagerRef> {
+        self.worlds.get(&world).map(|world| &world.entities)
+    }
+
+    pub fn world_ids(&self) -> impl Iterator<Item = WorldId> + '_ {
+        self.worlds.keys().copied()
+    }
+
+    /// Moves the entity from one world to another and returns its id in the new world,
+    /// see `EntityManager::move_entity_to`.
+    /// Takes the write lock of both worlds, systems should call it outside of their step
+    pub fn move_entity(
+        &self,
+        entity: EntityID,
+        from: WorldId,
+        to: WorldId,
+    ) -> Result<EntityID, EntityError> {
+        let source = self.world(from).ok_or(EntityError::UnknownWorld(from))?;
+        let target = self.world(to).ok_or(EntityError::UnknownWorld(to))?;
+
+        if from == to {
+            return match source.read().unwrap().is_alive(entity) {
+                true => Ok(entity),
+                false => Err(EntityError::DeadEntity(entity)),
+            };
         }
+
+        //Locked in id order so two moves in opposite directions can't deadlock
+        let (mut source, mut target) = match from < to {
+            true => {
+                let source = source.write().unwrap();
+                (source, target.write().unwrap())
+            }
+            false => {
+                let target = target.write().unwrap();
+                (source.write().unwrap(), target)
+            }
+        };
+
+        source.move_entity_to(entity, &mut target)
     }
 
-    pub fn get_events() {
-        // self
+    /// Systems that were declared to contend for the same components, see `SystemTrait::access`
+    pub fn access_conflicts(&self) -> Vec<AccessConflict> {
+        let mut conflicts = self.fixed_systems.access_conflicts.clone();
+        conflicts.extend(self.variable_systems.access_conflicts.iter().cloned());
+        conflicts
     }
 
-    fn setup_systems(&mut self, systems: Vec<System>) {
-        //Divide the systems across the available systems threads
-        //Give the render system thread its own dedicated thread
-        let systems_manager = self.systems_manager.clone();
-        let mut systems_manager_lock = systems_manager.write().unwrap();
+    /// Timing of the current frame, also available in every world as a resource
+    pub fn time(&self) -> Time {
+        self.clock.time()
+    }
 
-        let size_of_systems = systems.len();
-        systems_manager_lock.no_of_systems = Some(size_of_systems);
+    /// Pool the systems run on. Systems can spread their own work over it,
+    /// see `Query::par_for_each`
+    pub fn task_pool(&self) -> &TaskPool {
+        &self.task_pool
+    }
 
-        for (i, mut systems) in systems
-            .into_iter()
-            .chunks(Self::events_channel_size(
-                size_of_systems,
-                systems_manager_lock.thread_count,
-            ))
-            .into_iter()
-            .map(|chunk| chunk.collect::<Vec<System>>())
-        {
-            let system_event_sender = systems_manager_lock.system_events_channel.0.clone();
+    /// Overwrites the engine managed resource in every world
+    fn set_resource<T: Resource + Copy>(&self, value: T) {
+        for world in self.worlds.values() {
+            let entities = world
+                .entities
+                .read()
+                .unwrap_or_else(PoisonError::into_inner);
+            if let Some(mut resource) = entities.resource_mut::<T>() {
+                *resource = value;
+            };
+        }
+    }
+
+    /// Steps the systems of a stage once with the current `Time`, then applies their commands
+    fn run_stage(&self, systems_manager: &SystemManager) -> Vec<SystemEvent> {
+        let time = self.clock.time();
+        self.set_resource(time);
+
+        let (events, commands) =
+            self.run_systems(systems_manager, time.stage_delta().as_millis() as usize);
 
-            let mut game_state_receiver = systems_manager_lock.game_state_broadcast_bus.add_rx();
-            let mut game_tick_receiver = systems_manager_lock.tick_broadcast_bus.add_rx();
+        //Sync point, structural changes recorded by the systems land here
+        for (world, mut buffer) in commands {
+            let mut entity_manager = self.worlds[&world]
+                .entities
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+            for error in buffer.apply(&mut entity_manager) {
+                eprintln!("Failed to apply command in {:?}: {:?}", world, error);
+            }
+        }
+
+        events
+    }
 
-            //Thread is crushing for some unknown reason
-            //Invalid writes to a memory address.
-            //The crash only appears when the debugger is active
+    /// Steps every system once. A system is queued as soon as the systems it comes after are done,
+    /// so systems without an order between them run in parallel.
+    /// Main thread systems run here while the pool works through the others.
+    /// In deterministic mode they all run here, in schedule order
+    fn run_systems(
+        &self,
+        systems_manager: &SystemManager,
+        time: usize,
+    ) -> (Vec<SystemEvent>, Vec<(WorldId, Commands)>) {
+        if self.deterministic {
+            for system in systems_manager.schedule.order().iter().copied() {
+                systems_manager.step(system, time, self);
+            }
+            return systems_manager.drain();
+        }
 
-            let entities_ref = self.entity_manager.clone();
+        let system_count = systems_manager.systems.len();
+        let (main_thread, main_thread_systems) = unbounded();
 
-            let engine_ref = self.clone();
+        let frame = Frame {
+            engine: self,
+            systems_manager,
+            time,
+            waiting: (0..system_count)
+                .map(|system| AtomicUsize::new(systems_manager.schedule.dependencies(system).len()))
+                .collect(),
+            finished: AtomicUsize::new(0),
+            main_thread,
+        };
 
-            let worker_handle = thread::spawn(move || {
-                for system in systems.iter_mut() {
-                    system.init();
+        self.task_pool.scope(|scope| {
+            for system in 0..system_count {
+                if systems_manager.schedule.dependencies(system).is_empty() {
+                    frame.dispatch(scope, system);
                 }
+            }
 
-                let mut event_buffer: Vec<SystemEvent> = vec![];
-                loop {
-                    let tick_time = game_tick_receiver.recv().unwrap();
-                    //println!("Updated: Thread {}", i);
-                    let _game_state_events = game_state_receiver.recv().unwrap();
-
-                    for system in systems.iter_mut() {
-                        match system.update(tick_time, &entities_ref, &engine_ref) {
-                            Ok(events) => event_buffer.extend(events),
-                            Err(_error) => {
-                                //TODO: Log the errors
-                            }
-                        }
-                    }
-
-                    //NOTE: (teddy) this is a temporary fix
-                    //Should invenstigate the blocking problem
-                    //FIXME: (teddy) We should crash the program incase the send operation failed.
-                    if let Ok(_) = system_event_sender
-                        .send_timeout(event_buffer.clone(), Duration::from_millis(16))
-                    {
-                        event_buffer.clear();
-                    }
+            while frame.finished.load(Ordering::Acquire) < system_count {
+                if let Ok(system) = main_thread_systems.try_recv() {
+                    frame.run(scope, system);
+                } else if !self.task_pool.run_pending() {
+                    yield_now();
                 }
-            });
+            }
+        });
 
-            let worker = Worker {
-                _join_handle: worker_handle,
-            };
+        systems_manager.drain()
+    }
 
-            systems_manager_lock.workers.push(worker);
-        }
+    pub fn get_events() {
+        // self
     }
 
-    fn setup_level(&mut self) {
-        self.level_manager.load_resources();
-        self.level_manager.create_entities(&self.entity_manager);
+    fn setup_level(&mut self, level_manager: &mut dyn LevelManager) {
+        level_manager.load_resources();
+        level_manager.create_entities(self.entity_manager());
     }
 }
 
 pub struct EngineBuilder {
-    systems: Vec<System>,
+    systems: Vec<(WorldId, System)>,
+    worlds: BTreeSet<WorldId>,
     level_manager: Option<Box<dyn LevelManager>>,
+    entity_limit: Option<usize>,
+    fixed_timestep: Duration,
+    /// Adds an event type to a world
+    events: Vec<fn(&mut EntityManager)>,
+    pacing: FramePacing,
+    target_frame_rate: u32,
+    deterministic: bool,
 }
 
 impl EngineBuilder {
     pub fn builder() -> Self {
         Self {
             systems: vec![],
+            worlds: BTreeSet::from([WorldId::MAIN]),
             level_manager: None,
+            entity_limit: None,
+            fixed_timestep: Duration::from_secs(1) / 60,
+            events: vec![],
+            pacing: FramePacing::default(),
+            target_frame_rate: 60,
+            deterministic: false,
         }
     }
 
-    pub fn add_system(mut self, system: System) -> Self {
-        self.systems.push(system);
+    /// Binds the system to the main world. Takes a `System`, any `SystemTrait` or a
+    /// `Box<dyn SystemTrait + Send>`, see `IntoSystem`
+    pub fn add_system(self, system: impl IntoSystem) -> Self {
+        self.add_system_to(WorldId::MAIN, system)
+    }
+
+    /// Binds the system to `world`, creating the world if needed.
+    /// The system only gets that world's `EntityManager` and its commands are applied there
+    pub fn add_system_to(mut self, world: WorldId, system: impl IntoSystem) -> Self {
+        self.worlds.insert(world);
+        self.systems.push((world, system.into_system()));
+        self
+    }
+
+    /// Creates an empty world, for worlds that only get edited through `Engine::world`
+    pub fn add_world(mut self, world: WorldId) -> Self {
+        self.worlds.insert(world);
         self
     }
 
@@ -306,27 +1215,746 @@ impl EngineBuilder {
         self
     }
 
+    /// Caps the number of live entities in each world. Unbounded by default
+    pub fn entity_limit(mut self, limit: usize) -> Self {
+        self.entity_limit = Some(limit);
+        self
+    }
+
+    /// Simulated time of one step of the fixed stage. 60 steps a second by default
+    pub fn fixed_timestep(mut self, timestep: Duration) -> Self {
+        assert!(!timestep.is_zero(), "The fixed timestep can't be zero");
+        self.fixed_timestep = timestep;
+        self
+    }
+
+    /// Adds the `Events<T>` resource to every world, see `EntityManager::add_event`
+    pub fn add_event<T: Event>(mut self) -> Self {
+        self.events.push(EntityManager::add_event::<T>);
+        self
+    }
+
+    /// How `Engine::run` paces the frames. Sleeps to the target frame rate by default
+    pub fn frame_pacing(mut self, pacing: FramePacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Frames per second `FramePacing::FixedSleep` aims for. 60 by default
+    pub fn target_frame_rate(mut self, frame_rate: u32) -> Self {
+        assert!(frame_rate > 0, "The target frame rate can't be zero");
+        self.target_frame_rate = frame_rate;
+        self
+    }
+
+    /// Runs every system on the thread calling `Engine::update`, one after the other in schedule
+    /// order, and moves the clock one fixed timestep per update whatever the wall clock says.
+    /// The task pool gets no threads, so `Query::par_for_each` runs its batches in order too.
+    /// Two engines built the same way and fed the same inputs end up in the same state,
+    /// for replays and regression tests
+    pub fn deterministic(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+
+    /// Panics if the systems can't be ordered, see `try_build`
     pub fn build(self) -> Engine {
+        self.try_build()
+            .unwrap_or_else(|error| panic!("Failed to build the engine: {}", error))
+    }
+
+    /// Fails if the `before` and `after` constraints of the systems form a cycle
+    /// or refer to a label no system of the same stage has
+    pub fn try_build(self) -> Result<Engine, ScheduleError> {
+        let clock = match self.deterministic {
+            true => Clock::simulated(self.fixed_timestep),
+            false => Clock::new(self.fixed_timestep),
+        };
+        //Only the render system waits for the display, a headless engine sleeps instead
+        let has_window = self
+            .systems
+            .iter()
+            .any(|(_, system)| matches!(system, System::RenderSystem(_)));
+        let pacing = match self.pacing {
+            FramePacing::Vsync if !has_window => FramePacing::FixedSleep,
+            pacing => pacing,
+        };
+        let (mut fixed, mut variable): (Vec<_>, Vec<_>) = self
+            .systems
+            .into_iter()
+            .partition(|(_, system)| system.stage() == Stage::Fixed);
+        let fixed_schedule = Schedule::new(&fixed)?;
+        let variable_schedule = Schedule::new(&variable)?;
+
         //TODO: (teddy) bind event
         //Get the thread count from operating system
         let mut sys = HardWareSystem::new_all();
         sys.refresh_cpu();
+        let worlds = self
+            .worlds
+            .iter()
+            .map(|id| {
+                let mut entities = EntityManager::with_entity_limit(self.entity_limit);
+                entities.insert_resource(Camera::new());
+                entities.insert_resource(Input::new());
+                entities.insert_resource(clock.time());
+                entities.insert_resource(FrameStats::default());
+                entities.insert_resource(pacing);
+                for add_event in self.events.iter() {
+                    add_event(&mut entities);
+                }
+
+                let world = World {
+                    entities: Arc::new(RwLock::new(entities)),
+                    previous_tick: 0,
+                };
+                (*id, world)
+            })
+            .collect();
+
+        //The thread calling `Engine::update` works through the frame as well
+        let thread_count = match self.deterministic {
+            true => 0,
+            false => sys.cpus().len().saturating_sub(1).max(1),
+        };
+
+        for (_, system) in fixed.iter_mut().chain(variable.iter_mut()) {
+            system.init();
+        }
+
         let mut engine = Engine {
-            systems_manager: Arc::new(RwLock::new(SystemManager::new(sys.cpus().len()))),
-            previous_tick: 0,
-            entity_manager: Arc::new(RwLock::new(EntityManager::new())),
-            camera: Arc::new(RwLock::new(Camera::new())),
+            fixed_systems: Arc::new(SystemManager::new(fixed, fixed_schedule)),
+            variable_systems: Arc::new(SystemManager::new(variable, variable_schedule)),
+            task_pool: Arc::new(TaskPool::new(thread_count)),
+            worlds,
+            clock,
+            frame_stats: FrameStats::default(),
+            pacing,
+            target_frame_rate: self.target_frame_rate,
+            deterministic: self.deterministic,
+            shutdown_requested: false,
         };
-        let temp = self.systems;
-        engine.setup_systems(temp);
-        engine.setup_level();
-        engine
+        for conflict in engine.access_conflicts() {
+            eprintln!(
+                "{} and {} are not ordered and both access {:?} in {:?}, they may wait on each other",
+                conflict.systems.0, conflict.systems.1, conflict.components, conflict.world
+            );
+        }
+
+        if let Some(mut level_manager) = self.level_manager {
+            engine.setup_level(level_manager.as_mut());
+        }
+        Ok(engine)
     }
 }
 
 //From the engines perspective...
 //The level manager will load the system and
+/// Entities created in `create_entities` can be named with `EntityManager::set_name`
+/// and wired together later through `EntityManager::find_by_name`
 pub trait LevelManager {
     fn load_resources(&mut self);
     fn create_entities(&mut self, entity_manager: &EntityManagerRef);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use std::time::{Duration, Instant};
+
+    use super::{
+        Engine, EngineBuilder, EntityError, EntityManager, EntityManagerRef, Events, SystemEvent,
+    };
+    use crate::core::{
+        commands::Commands,
+        components::{Component, Name, StorageType},
+        events::EventReader,
+        query::{Access, Query},
+        schedule::Stage,
+        system::{SysResult, SystemTrait},
+        time::{FramePacing, FrameStats, Time},
+    };
+
+    /// Asks for the engine to stop on its third step
+    struct StopAfterThreeSteps {
+        steps: Arc<AtomicUsize>,
+        shutdowns: Arc<AtomicUsize>,
+    }
+
+    impl SystemTrait for StopAfterThreeSteps {
+        fn shutdown(&mut self) {
+            self.shutdowns.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn step(
+            &mut self,
+            _time: usize,
+            _entities: &EntityManagerRef,
+            _commands: &mut Commands,
+            _engine: &Engine,
+        ) -> SysResult<Vec<SystemEvent>> {
+            match self.steps.fetch_add(1, Ordering::SeqCst) {
+                2 => Ok(vec![SystemEvent::ShutdownEngine]),
+                _ => Ok(vec![]),
+            }
+        }
+    }
+
+    struct PanicOnShutdown;
+
+    impl SystemTrait for PanicOnShutdown {
+        fn shutdown(&mut self) {
+            panic!("Failed to clean up");
+        }
+
+        fn step(
+            &mut self,
+            _time: usize,
+            _entities: &EntityManagerRef,
+            _commands: &mut Commands,
+            _engine: &Engine,
+        ) -> SysResult<Vec<SystemEvent>> {
+            Ok(vec![])
+        }
+    }
+
+    /// Panics while holding the write lock of its world
+    struct PanicWhileWriting;
+
+    impl SystemTrait for PanicWhileWriting {
+        fn step(
+            &mut self,
+            _time: usize,
+            entities: &EntityManagerRef,
+            _commands: &mut Commands,
+            _engine: &Engine,
+        ) -> SysResult<Vec<SystemEvent>> {
+            let _entities = entities.write().unwrap();
+            panic!("Failed while writing");
+        }
+
+        fn labels(&self) -> Vec<&'static str> {
+            vec!["writer"]
+        }
+    }
+
+    /// Reads its world every step, after `PanicWhileWriting`
+    struct ReadAfterWriter(Arc<AtomicUsize>);
+
+    impl SystemTrait for ReadAfterWriter {
+        fn step(
+            &mut self,
+            _time: usize,
+            entities: &EntityManagerRef,
+            _commands: &mut Commands,
+            _engine: &Engine,
+        ) -> SysResult<Vec<SystemEvent>> {
+            let _entities = entities.read().unwrap();
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![])
+        }
+
+        fn after(&self) -> Vec<&'static str> {
+            vec!["writer"]
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Position(f32);
+
+    impl Component for Position {
+        fn snapshot(&self) -> Option<Self> {
+            Some(*self)
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Health(i32);
+
+    impl Component for Health {
+        const STORAGE: StorageType = StorageType::Table;
+    }
+
+    /// Spawns an entity every step and moves all of them with float math
+    struct Simulation;
+
+    impl SystemTrait for Simulation {
+        fn stage(&self) -> Stage {
+            Stage::Fixed
+        }
+
+        fn step(
+            &mut self,
+            _time: usize,
+            entities: &EntityManagerRef,
+            commands: &mut Commands,
+            engine: &Engine,
+        ) -> SysResult<Vec<SystemEvent>> {
+            let entities = entities.read().unwrap();
+            let delta = entities
+                .resource::<Time>()
+                .unwrap()
+                .fixed_delta
+                .as_secs_f32();
+            let mut query: Query<'_, &mut Position> = entities.query();
+            query.par_for_each(engine.task_pool(), 2, |mut position| {
+                position.0 = position.0 * 1.01 + delta.sin();
+            });
+
+            commands.spawn().insert(Position(0.1));
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn deterministic_runs_match() {
+        let run = || {
+            let mut engine = EngineBuilder::builder()
+                .deterministic()
+                .add_system(Simulation)
+                .build();
+            for _ in 0..20 {
+                engine.update();
+            }
+            engine
+        };
+        let (first, second) = (run(), run());
+
+        let first_world = first.entity_manager().read().unwrap();
+        let second_world = second.entity_manager().read().unwrap();
+        let positions = |world: &super::EntityManager| {
+            world
+                .entities()
+                .map(|entity| (entity, world.get_component::<Position>(entity).map(|p| *p)))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(positions(&first_world).len(), 20);
+        assert_eq!(positions(&first_world), positions(&second_world));
+        assert!(first_world
+            .snapshot()
+            .diff(&second_world.snapshot())
+            .is_empty());
+        assert_eq!(first_world.change_tick(), second_world.change_tick());
+        assert_eq!(first.time(), second.time());
+        assert_eq!(first.time().elapsed, Duration::from_secs(1) / 60 * 20);
+    }
+
+    #[test]
+    fn headless_engine_starts_and_stops() {
+        let steps = Arc::new(AtomicUsize::new(0));
+        let shutdowns = Arc::new(AtomicUsize::new(0));
+        let mut engine = EngineBuilder::builder()
+            .add_system(StopAfterThreeSteps {
+                steps: steps.clone(),
+                shutdowns: shutdowns.clone(),
+            })
+            .build();
+
+        while !engine.shutdown_requested() {
+            engine.update();
+        }
+        let status = engine.shutdown(Duration::from_secs(5));
+
+        assert_eq!(steps.load(Ordering::SeqCst), 3);
+        assert_eq!(shutdowns.load(Ordering::SeqCst), 1);
+        assert!(status.success(), "{:?}", status);
+    }
+
+    #[test]
+    fn run_paces_frames_until_shutdown() {
+        let mut engine = EngineBuilder::builder()
+            .frame_pacing(FramePacing::FixedSleep)
+            .target_frame_rate(100)
+            .add_system(StopAfterThreeSteps {
+                steps: Arc::new(AtomicUsize::new(0)),
+                shutdowns: Arc::new(AtomicUsize::new(0)),
+            })
+            .build();
+
+        let start = Instant::now();
+        engine.run();
+
+        //The frame budget is slept after the first two frames, the loop ends right after the third
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let stats = *engine
+            .entity_manager()
+            .read()
+            .unwrap()
+            .resource::<FrameStats>()
+            .unwrap();
+        assert_eq!(stats, engine.frame_stats());
+        assert_eq!(stats.frames, 3);
+        //A late frame isn't made up for, so only one of the two is sure to get the full budget
+        assert!(stats.max_frame_time >= Duration::from_millis(9));
+        assert!(engine.shutdown(Duration::from_secs(5)).success());
+    }
+
+    #[test]
+    fn headless_vsync_falls_back_to_sleeping() {
+        let mut engine = EngineBuilder::builder()
+            .frame_pacing(FramePacing::Vsync)
+            .target_frame_rate(100)
+            .add_system(StopAfterThreeSteps {
+                steps: Arc::new(AtomicUsize::new(0)),
+                shutdowns: Arc::new(AtomicUsize::new(0)),
+            })
+            .build();
+
+        let start = Instant::now();
+        engine.run();
+
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let pacing = *engine
+            .entity_manager()
+            .read()
+            .unwrap()
+            .resource::<FramePacing>()
+            .unwrap();
+        assert_eq!(pacing, FramePacing::FixedSleep);
+        assert!(engine.shutdown(Duration::from_secs(5)).success());
+    }
+
+    /// Sends a thousand events every step
+    struct Chatty;
+
+    impl SystemTrait for Chatty {
+        fn step(
+            &mut self,
+            _time: usize,
+            _entities: &EntityManagerRef,
+            _commands: &mut Commands,
+            _engine: &Engine,
+        ) -> SysResult<Vec<SystemEvent>> {
+            Ok(vec![SystemEvent::AssetSystemEvent; 1000])
+        }
+    }
+
+    #[test]
+    fn every_event_is_handled_in_its_frame() {
+        let mut engine = (0..8)
+            .fold(EngineBuilder::builder(), |builder, _| {
+                builder.add_system(Chatty)
+            })
+            .build();
+
+        for frame in 1..=10 {
+            engine.update();
+
+            let stats = engine.frame_stats();
+            assert_eq!(stats.frames, frame);
+            assert_eq!(stats.events, 8 * 1000);
+        }
+        assert!(engine.shutdown(Duration::from_secs(5)).success());
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Tick(usize);
+
+    struct SendTicks(usize);
+
+    impl SystemTrait for SendTicks {
+        fn labels(&self) -> Vec<&'static str> {
+            vec!["send"]
+        }
+
+        fn access(&self) -> Access {
+            Access::default().write_resource::<Events<Tick>>()
+        }
+
+        fn step(
+            &mut self,
+            _time: usize,
+            entities: &EntityManagerRef,
+            _commands: &mut Commands,
+            _engine: &Engine,
+        ) -> SysResult<Vec<SystemEvent>> {
+            entities.read().unwrap().send_event(Tick(self.0));
+            self.0 += 1;
+            Ok(vec![])
+        }
+    }
+
+    struct ReadTicks {
+        stage: Stage,
+        after_send: bool,
+        reader: EventReader<Tick>,
+        read: Arc<std::sync::Mutex<Vec<usize>>>,
+    }
+
+    impl SystemTrait for ReadTicks {
+        fn stage(&self) -> Stage {
+            self.stage
+        }
+
+        fn after(&self) -> Vec<&'static str> {
+            match self.after_send {
+                true => vec!["send"],
+                false => vec![],
+            }
+        }
+
+        fn step(
+            &mut self,
+            _time: usize,
+            entities: &EntityManagerRef,
+            _commands: &mut Commands,
+            _engine: &Engine,
+        ) -> SysResult<Vec<SystemEvent>> {
+            let entities = entities.read().unwrap();
+            let events = entities.events::<Tick>().unwrap();
+            let mut read = self.read.lock().unwrap();
+            read.extend(self.reader.read(&events).map(|tick| tick.0));
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn every_reader_sees_every_event_once() {
+        let readers = (0..4)
+            .map(|_| Arc::new(std::sync::Mutex::new(vec![])))
+            .collect::<Vec<_>>();
+        let mut engine = readers
+            .iter()
+            .enumerate()
+            .fold(EngineBuilder::builder(), |builder, (i, read)| {
+                builder.add_system(ReadTicks {
+                    stage: Stage::Variable,
+                    after_send: i % 2 == 0,
+                    reader: EventReader::new(),
+                    read: read.clone(),
+                })
+            })
+            .add_system(SendTicks(0))
+            .add_event::<Tick>()
+            .build();
+
+        for _ in 0..50 {
+            engine.update();
+        }
+
+        for (i, read) in readers.iter().enumerate() {
+            let read = read.lock().unwrap();
+            //Readers that may run before the sender get the last tick next frame
+            let expected = match i % 2 {
+                0 => 50,
+                _ => read.len().max(49),
+            };
+            assert_eq!(*read, (0..expected).collect::<Vec<_>>());
+        }
+        assert!(engine.shutdown(Duration::from_secs(5)).success());
+    }
+
+    #[test]
+    fn events_swap_even_when_the_fixed_stage_is_skipped() {
+        let fixed = Arc::new(std::sync::Mutex::new(vec![]));
+        let variable = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut engine = EngineBuilder::builder()
+            .add_system(ReadTicks {
+                stage: Stage::Fixed,
+                after_send: false,
+                reader: EventReader::new(),
+                read: fixed.clone(),
+            })
+            .add_system(ReadTicks {
+                stage: Stage::Variable,
+                after_send: true,
+                reader: EventReader::new(),
+                read: variable.clone(),
+            })
+            .add_system(SendTicks(0))
+            .add_event::<Tick>()
+            //Too long for any of the frames to run a fixed step
+            .fixed_timestep(Duration::from_secs(3600))
+            .build();
+
+        for _ in 0..20 {
+            engine.update();
+
+            //Only the events of the current and previous frame are kept
+            let entities = engine.entity_manager().read().unwrap();
+            assert!(entities.events::<Tick>().unwrap().len() <= 2);
+        }
+
+        assert!(fixed.lock().unwrap().is_empty());
+        assert_eq!(*variable.lock().unwrap(), (0..20).collect::<Vec<_>>());
+        assert!(engine.shutdown(Duration::from_secs(5)).success());
+    }
+
+    #[test]
+    fn engine_without_systems_stops() {
+        let mut engine = EngineBuilder::builder().build();
+        engine.update();
+
+        assert!(!engine.shutdown_requested());
+        assert!(engine.shutdown(Duration::from_secs(5)).success());
+    }
+
+    #[test]
+    fn panicking_shutdown_hook_is_reported() {
+        let shutdowns = Arc::new(AtomicUsize::new(0));
+        let engine = EngineBuilder::builder()
+            .add_system(PanicOnShutdown)
+            .add_system(StopAfterThreeSteps {
+                steps: Arc::new(AtomicUsize::new(0)),
+                shutdowns: shutdowns.clone(),
+            })
+            .build();
+
+        let status = engine.shutdown(Duration::from_secs(5));
+
+        assert!(!status.success());
+        assert_eq!(status.panicked.len(), 1);
+        assert!(status.panicked[0].contains("PanicOnShutdown"));
+        //The other systems still get shut down
+        assert_eq!(shutdowns.load(Ordering::SeqCst), 1);
+        assert_eq!(status.detached_threads, 0);
+    }
+
+    #[test]
+    fn names_stay_indexed_however_they_are_inserted() {
+        let mut entities = EntityManager::new();
+        let a = entities.create_entity();
+        entities.insert_component(a, Name("a".into())).unwrap();
+        assert_eq!(entities.find_by_name("a"), Some(a));
+
+        //Renaming frees the previous name
+        entities.insert_component(a, Name("b".into())).unwrap();
+        assert_eq!(entities.find_by_name("a"), None);
+        assert_eq!(entities.find_by_name("b"), Some(a));
+
+        let c = entities.spawn_bundle((Name("c".into()),)).unwrap();
+        assert_eq!(entities.find_by_name("c"), Some(c));
+
+        let mut commands = Commands::new();
+        commands.spawn().insert(Name("d".into()));
+        assert!(commands.apply(&mut entities).is_empty());
+        let d = entities.find_by_name("d").unwrap();
+        assert_eq!(entities.name(d).as_deref(), Some("d"));
+
+        //A clash leaves both entities as they were
+        assert_eq!(
+            entities.insert_component(d, Name("b".into())),
+            Err(EntityError::NameTaken(a))
+        );
+        assert_eq!(entities.name(d).as_deref(), Some("d"));
+
+        //Nothing of a batch is spawned when one of its names is taken
+        let count = entities.entities().count();
+        assert_eq!(
+            entities.spawn_batch([(Name("e".into()),), (Name("c".into()),)]),
+            Err(EntityError::NameTaken(c))
+        );
+        assert_eq!(entities.entities().count(), count);
+        assert_eq!(entities.find_by_name("e"), None);
+    }
+
+    #[test]
+    fn moved_entities_keep_their_components_and_name() {
+        let mut source = EntityManager::new();
+        let mut target = EntityManager::new();
+        let entity = source.create_entity();
+        source.insert_component(entity, Health(3)).unwrap();
+        source.insert_component(entity, Position(1.0)).unwrap();
+        source.set_name(entity, "player").unwrap();
+
+        let moved = source.move_entity_to(entity, &mut target).unwrap();
+        assert_eq!(*target.get_component::<Health>(moved).unwrap(), Health(3));
+        assert_eq!(
+            *target.get_component::<Position>(moved).unwrap(),
+            Position(1.0)
+        );
+        assert_eq!(target.find_by_name("player"), Some(moved));
+
+        //The source slot is free for the next entity
+        assert!(!source.is_alive(entity));
+        assert_eq!(source.entities().count(), 0);
+        assert_eq!(source.find_by_name("player"), None);
+        assert_eq!(source.create_entity().id(), entity.id());
+    }
+
+    #[test]
+    fn name_clash_moves_nothing() {
+        let mut source = EntityManager::new();
+        let mut target = EntityManager::new();
+        let entity = source.create_entity();
+        source.insert_component(entity, Health(3)).unwrap();
+        source.set_name(entity, "player").unwrap();
+        let owner = target.create_entity();
+        target.set_name(owner, "player").unwrap();
+
+        assert_eq!(
+            source.move_entity_to(entity, &mut target),
+            Err(EntityError::NameTaken(owner))
+        );
+        assert_eq!(*source.get_component::<Health>(entity).unwrap(), Health(3));
+        assert_eq!(source.find_by_name("player"), Some(entity));
+        assert_eq!(target.entities().collect::<Vec<_>>(), [owner]);
+    }
+
+    #[test]
+    fn entity_limit_counts_freed_slots() {
+        let mut entities = EntityManager::with_entity_limit(Some(3));
+        let first = entities.create_entity();
+        entities.create_entity();
+        entities.create_entity();
+        assert_eq!(
+            entities.try_create_entity(),
+            Err(EntityError::LimitReached(3))
+        );
+
+        //A destroyed entity frees its slot for the next one
+        entities.destroy_entity(first);
+        let reused = entities.try_create_entity().unwrap();
+        assert_eq!(reused.id(), first.id());
+        assert!(!entities.is_alive(first));
+        assert_eq!(
+            entities.try_create_entity(),
+            Err(EntityError::LimitReached(3))
+        );
+    }
+
+    #[test]
+    fn batch_over_the_limit_spawns_nothing() {
+        let mut entities = EntityManager::with_entity_limit(Some(3));
+        let kept = entities.create_entity();
+        let freed = entities.create_entity();
+        entities.destroy_entity(freed);
+
+        //The freed slot would be reused, but the two new ones go past the limit
+        assert_eq!(
+            entities.spawn_batch([(Health(1),), (Health(2),), (Health(3),)]),
+            Err(EntityError::LimitReached(3))
+        );
+        assert_eq!(entities.entities().collect::<Vec<_>>(), [kept]);
+
+        let spawned = entities.spawn_batch([(Health(1),), (Health(2),)]).unwrap();
+        assert_eq!(spawned[0].id(), freed.id());
+        assert_eq!(entities.entities().count(), 3);
+        assert_eq!(
+            *entities.get_component::<Health>(spawned[1]).unwrap(),
+            Health(2)
+        );
+    }
+
+    #[test]
+    fn panic_holding_the_world_lock_doesnt_stop_the_frame() {
+        let steps = Arc::new(AtomicUsize::new(0));
+        let mut engine = EngineBuilder::builder()
+            .add_system(PanicWhileWriting)
+            .add_system(ReadAfterWriter(steps.clone()))
+            .build();
+
+        for _ in 0..3 {
+            engine.update();
+        }
+
+        //The system waiting on the panicking one still runs every frame
+        assert_eq!(steps.load(Ordering::SeqCst), 3);
+        assert!(!engine.entity_manager().is_poisoned());
+        assert_eq!(engine.frame_stats().frames, 3);
+    }
+}
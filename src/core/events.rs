@@ -0,0 +1,177 @@
+use std::{any::Any, marker::PhantomData};
+
+use super::resources::ResMut;
+
+/// Any type systems send to each other through `EventWriter` and `EventReader`
+pub trait Event: Any + Send + Sync {}
+
+impl<T: Any + Send + Sync> Event for T {}
+
+/// Events of one type sent during the current and the previous frame.
+/// Every world has one as a resource for each type added with `EntityManager::add_event`.
+/// The buffers swap at the end of every frame, so a fixed system that goes more than
+/// a frame without a step misses the events sent in between
+/// ```ignore
+/// struct Collision(EntityID, EntityID);
+///
+/// entities.add_event::<Collision>();
+/// entities.send_event(Collision(a, b));
+/// ```
+pub struct Events<T> {
+    /// Sent before the latest swap
+    previous: Vec<T>,
+    /// Sent since the latest swap
+    current: Vec<T>,
+    /// Events dropped so far, the id of the first event of `previous`
+    start: usize,
+}
+
+impl<T: Event> Events<T> {
+    pub fn new() -> Self {
+        Self {
+            previous: vec![],
+            current: vec![],
+            start: 0,
+        }
+    }
+
+    pub fn send(&mut self, event: T) {
+        self.current.push(event);
+    }
+
+    pub fn send_batch(&mut self, events: impl IntoIterator<Item = T>) {
+        self.current.extend(events);
+    }
+
+    /// Events still buffered
+    pub fn len(&self) -> usize {
+        self.previous.len() + self.current.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops the events of the previous frame, the current ones become the previous ones
+    pub(crate) fn update(&mut self) {
+        self.start += self.previous.len();
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    /// Id the next event sent gets
+    fn end(&self) -> usize {
+        self.start + self.len()
+    }
+}
+
+impl<T: Event> Default for Events<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write access to the events of one type, see `EntityManager::event_writer`.
+/// Holds the lock of `Events<T>` until dropped, declare it with `Access::write_resource`
+pub struct EventWriter<'w, T: Event> {
+    events: ResMut<'w, Events<T>>,
+}
+
+impl<'w, T: Event> EventWriter<'w, T> {
+    pub(crate) fn new(events: ResMut<'w, Events<T>>) -> Self {
+        Self { events }
+    }
+
+    pub fn send(&mut self, event: T) {
+        self.events.send(event);
+    }
+
+    pub fn send_batch(&mut self, events: impl IntoIterator<Item = T>) {
+        self.events.send_batch(events);
+    }
+}
+
+/// Remembers which events a system already read. Each system keeps its own reader,
+/// so every reader gets every event once whichever thread its system runs on
+/// ```ignore
+/// let events = entities.events::<Collision>().unwrap();
+/// for collision in self.collisions.read(&events) {
+///     ...
+/// }
+/// ```
+pub struct EventReader<T> {
+    /// Id of the first event not read yet
+    next: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Event> EventReader<T> {
+    /// The reader starts with the events still buffered
+    pub fn new() -> Self {
+        Self {
+            next: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Events sent since the previous read, oldest first.
+    /// Events are dropped after two swaps, a reader that waits longer misses them
+    pub fn read<'a>(&mut self, events: &'a Events<T>) -> impl Iterator<Item = &'a T> {
+        let skip = self.next.saturating_sub(events.start);
+        self.next = events.end();
+
+        events
+            .previous
+            .iter()
+            .chain(events.current.iter())
+            .skip(skip)
+    }
+
+    /// Marks every buffered event as read
+    pub fn clear(&mut self, events: &Events<T>) {
+        self.next = events.end();
+    }
+}
+
+impl<T: Event> Default for EventReader<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventReader, EventWriter, Events};
+    use crate::core::resources::Resources;
+
+    #[test]
+    fn readers_see_every_event_once() {
+        let mut events = Events::new();
+        let mut early = EventReader::new();
+        let mut late = EventReader::new();
+
+        events.send(1);
+        assert_eq!(early.read(&events).collect::<Vec<_>>(), [&1]);
+        events.send_batch([2, 3]);
+        events.update();
+
+        //Events of the previous frame are still there for readers that haven't seen them
+        assert_eq!(early.read(&events).collect::<Vec<_>>(), [&2, &3]);
+        assert_eq!(late.read(&events).collect::<Vec<_>>(), [&1, &2, &3]);
+        assert_eq!(late.read(&events).count(), 0);
+
+        //Systems write through the lock of the resource
+        let mut resources = Resources::new();
+        resources.insert(events);
+        EventWriter::new(resources.get_mut::<Events<i32>>().unwrap()).send_batch([4, 5]);
+        let mut events = resources.remove::<Events<i32>>().unwrap();
+        assert_eq!(late.read(&events).collect::<Vec<_>>(), [&4, &5]);
+
+        events.send(6);
+        late.clear(&events);
+        assert_eq!(late.read(&events).count(), 0);
+        events.update();
+        events.update();
+        assert!(events.is_empty());
+        assert_eq!(early.read(&events).count(), 0);
+    }
+}
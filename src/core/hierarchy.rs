@@ -0,0 +1,290 @@
+use nalgebra::Matrix4;
+
+use super::{
+    components::{Component, TransformComponent},
+    engine::{EntityError, EntityID, EntityManager},
+};
+
+/// Entity this entity is attached to. Only set through `EntityManager::set_parent`
+/// so that the parent's `Children` stays in sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parent(EntityID);
+
+impl Parent {
+    pub fn get(&self) -> EntityID {
+        self.0
+    }
+}
+
+/// Entities attached to this entity, in the order they were attached
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Children(Vec<EntityID>);
+
+impl Children {
+    pub fn as_slice(&self) -> &[EntityID] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Component for Parent {
+    fn snapshot(&self) -> Option<Self> {
+        Some(*self)
+    }
+}
+
+impl Component for Children {
+    fn snapshot(&self) -> Option<Self> {
+        Some(self.clone())
+    }
+}
+
+impl EntityManager {
+    /// Attaches `child` to `parent`, detaching it from its previous parent first.
+    /// Fails if `parent` is `child` itself or one of its descendants
+    pub fn set_parent(&mut self, child: EntityID, parent: EntityID) -> Result<(), EntityError> {
+        for entity in [child, parent] {
+            if !self.is_alive(entity) {
+                return Err(EntityError::DeadEntity(entity));
+            }
+        }
+
+        if child == parent || self.is_ancestor(child, parent) {
+            return Err(EntityError::HierarchyCycle(child));
+        }
+
+        self.remove_parent(child);
+        self.insert_component(child, Parent(parent))?;
+
+        let attached = self
+            .get_component_mut::<Children>(parent)
+            .map(|mut children| children.0.push(child))
+            .is_some();
+
+        if !attached {
+            self.insert_component(parent, Children(vec![child]))?;
+        }
+
+        Ok(())
+    }
+
+    /// Detaches `child` from its parent, making it a root. Returns the old parent
+    pub fn remove_parent(&mut self, child: EntityID) -> Option<EntityID> {
+        let Parent(parent) = self.remove_component::<Parent>(child)?;
+
+        if let Some(mut children) = self.get_component_mut::<Children>(parent) {
+            children.0.retain(|entity| *entity != child);
+        }
+
+        Some(parent)
+    }
+
+    pub fn parent(&self, entity: EntityID) -> Option<EntityID> {
+        self.get_component::<Parent>(entity).map(|parent| parent.0)
+    }
+
+    pub fn children(&self, entity: EntityID) -> Vec<EntityID> {
+        self.get_component::<Children>(entity)
+            .map(|children| children.0.clone())
+            .unwrap_or_default()
+    }
+
+    /// Walks up the parents of `entity` looking for `ancestor`
+    pub fn is_ancestor(&self, ancestor: EntityID, entity: EntityID) -> bool {
+        let mut current = self.parent(entity);
+        while let Some(parent) = current {
+            if parent == ancestor {
+                return true;
+            }
+
+            current = self.parent(parent);
+        }
+
+        false
+    }
+
+    /// Destroys the entity along with all of its descendants.
+    /// Returns false if the handle is stale
+    pub fn destroy_entity_recursive(&mut self, entity: EntityID) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+
+        let mut stack = vec![entity];
+        let mut descendants = vec![];
+        while let Some(current) = stack.pop() {
+            stack.extend(self.children(current));
+            descendants.push(current);
+        }
+
+        //Children first so every entity is detached from a live parent
+        for entity in descendants.into_iter().rev() {
+            self.destroy_entity(entity);
+        }
+
+        true
+    }
+
+    /// Unlinks the entity from its parent and turns its children into roots.
+    /// Called by `destroy_entity` before the components are dropped
+    pub(crate) fn detach_hierarchy(&mut self, entity: EntityID) {
+        self.remove_parent(entity);
+
+        if let Some(Children(children)) = self.remove_component::<Children>(entity) {
+            for child in children {
+                self.remove_component::<Parent>(child);
+            }
+        }
+    }
+}
+
+/// Recomputes the world matrix of every `TransformComponent` from the root entities down.
+/// Entities in the hierarchy without a transform pass their parent's matrix through unchanged
+pub fn propagate_transforms(entities: &EntityManager) {
+    let mut query = entities.query::<(
+        EntityID,
+        Option<&mut TransformComponent>,
+        Option<&Parent>,
+        Option<&Children>,
+    )>();
+
+    let mut stack: Vec<(EntityID, Matrix4<f32>)> = query
+        .iter()
+        .filter(|(_, transform, parent, children)| {
+            parent.is_none() && (transform.is_some() || children.is_some())
+        })
+        .map(|(entity, ..)| (entity, Matrix4::identity()))
+        .collect();
+
+    while let Some((entity, parent_matrix)) = stack.pop() {
+        let Some((_, transform, _, children)) = query.get(entity) else {
+            continue;
+        };
+
+        let world_matrix = match transform {
+            Some(mut transform) => {
+                let world_matrix = parent_matrix * transform.local_matrix();
+                //Only touch transforms that moved so `Changed<TransformComponent>` stays meaningful
+                if transform.world_matrix() != world_matrix {
+                    transform.set_world_matrix(world_matrix);
+                }
+                world_matrix
+            }
+            None => parent_matrix,
+        };
+
+        if let Some(children) = children {
+            stack.extend(children.0.iter().map(|child| (*child, world_matrix)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::propagate_transforms;
+    use crate::core::{
+        components::TransformComponent,
+        engine::{EntityError, EntityManager},
+    };
+
+    #[test]
+    fn cycles_are_rejected() {
+        let mut entities = EntityManager::new();
+        let a = entities.create_entity();
+        let b = entities.create_entity();
+        let c = entities.create_entity();
+        entities.set_parent(a, b).unwrap();
+        entities.set_parent(b, c).unwrap();
+
+        assert_eq!(
+            entities.set_parent(b, a),
+            Err(EntityError::HierarchyCycle(b))
+        );
+        //Through a grandparent too
+        assert_eq!(
+            entities.set_parent(c, a),
+            Err(EntityError::HierarchyCycle(c))
+        );
+        assert_eq!(
+            entities.set_parent(a, a),
+            Err(EntityError::HierarchyCycle(a))
+        );
+        assert_eq!(entities.parent(b), Some(c));
+    }
+
+    #[test]
+    fn reparenting_moves_the_child() {
+        let mut entities = EntityManager::new();
+        let old = entities.create_entity();
+        let new = entities.create_entity();
+        let first = entities.create_entity();
+        let second = entities.create_entity();
+        entities.set_parent(first, old).unwrap();
+        entities.set_parent(second, old).unwrap();
+
+        entities.set_parent(first, new).unwrap();
+        assert_eq!(entities.children(old), [second]);
+        assert_eq!(entities.children(new), [first]);
+        assert_eq!(entities.parent(first), Some(new));
+    }
+
+    #[test]
+    fn children_are_placed_relative_to_their_parent() {
+        let mut entities = EntityManager::new();
+        let parent = entities.create_entity();
+        let child = entities.create_entity();
+        entities
+            .insert_component(
+                parent,
+                TransformComponent::from_translation(Vector3::new(1.0, 2.0, 0.0)),
+            )
+            .unwrap();
+        entities
+            .insert_component(
+                child,
+                TransformComponent::from_translation(Vector3::new(0.0, 1.0, 3.0)),
+            )
+            .unwrap();
+        entities.set_parent(child, parent).unwrap();
+
+        propagate_transforms(&entities);
+
+        let world_matrix = entities
+            .get_component::<TransformComponent>(child)
+            .unwrap()
+            .world_matrix();
+        assert_eq!(
+            world_matrix.fixed_view::<3, 1>(0, 3).into_owned(),
+            Vector3::new(1.0, 3.0, 3.0)
+        );
+    }
+
+    #[test]
+    fn recursive_destroy_reaches_grandchildren() {
+        let mut entities = EntityManager::new();
+        let root = entities.create_entity();
+        let child = entities.create_entity();
+        let grandchild = entities.create_entity();
+        let parent = entities.create_entity();
+        entities.set_parent(child, root).unwrap();
+        entities.set_parent(grandchild, child).unwrap();
+        entities.set_parent(root, parent).unwrap();
+
+        assert!(entities.destroy_entity_recursive(root));
+        for entity in [root, child, grandchild] {
+            assert!(!entities.is_alive(entity));
+        }
+        //The parent of the destroyed entity stays, without it
+        assert!(entities.is_alive(parent));
+        assert!(entities.children(parent).is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::HashSet;
+
+use glfw::{Action, Key, WindowEvent};
+
+/// Keyboard state and the window events of the latest poll. Every world has it as a resource,
+/// the render system fills the one of its world every time it polls the window
+/// ```ignore
+/// let input = entities.resource::<Input>().unwrap();
+/// if input.is_pressed(Key::Space) {}
+/// ```
+#[derive(Debug, Default)]
+pub struct Input {
+    pressed: HashSet<Key>,
+    events: Vec<WindowEvent>,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Held down since its press event, until its release event
+    pub fn is_pressed(&self, key: Key) -> bool {
+        self.pressed.contains(&key)
+    }
+
+    /// Window events of the latest poll, oldest first
+    pub fn events(&self) -> &[WindowEvent] {
+        &self.events
+    }
+
+    /// Replaces the events of the previous poll
+    pub(crate) fn update(&mut self, events: impl IntoIterator<Item = WindowEvent>) {
+        self.events.clear();
+
+        for event in events {
+            match event {
+                WindowEvent::Key(key, _, Action::Press, _) => {
+                    self.pressed.insert(key);
+                }
+                WindowEvent::Key(key, _, Action::Release, _) => {
+                    self.pressed.remove(&key);
+                }
+                _ => (),
+            }
+            self.events.push(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glfw::{Action, Key, Modifiers, WindowEvent};
+
+    use super::Input;
+
+    fn key(key: Key, action: Action) -> WindowEvent {
+        WindowEvent::Key(key, 0, action, Modifiers::empty())
+    }
+
+    #[test]
+    fn keys_stay_pressed_until_released() {
+        let mut input = Input::new();
+
+        input.update([key(Key::Up, Action::Press), key(Key::Left, Action::Press)]);
+        assert!(input.is_pressed(Key::Up));
+        assert_eq!(input.events().len(), 2);
+
+        //A poll without events keeps the keys held down
+        input.update([]);
+        assert!(input.is_pressed(Key::Left));
+        assert!(input.events().is_empty());
+
+        input.update([key(Key::Up, Action::Release)]);
+        assert!(!input.is_pressed(Key::Up));
+        assert!(input.is_pressed(Key::Left));
+    }
+}
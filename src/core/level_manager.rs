@@ -1,15 +1,9 @@
 use super::engine::LevelManager;
 
-
-pub struct StarterLevel {
-}
+pub struct StarterLevel {}
 
 impl LevelManager for StarterLevel {
-    fn load_resources(&mut self) {
-        
-    }
+    fn load_resources(&mut self) {}
 
-    fn create_entities(&mut self, _entity_manager: &super::engine::EntityManagerRef) {
-        
-    }
-}
\ No newline at end of file
+    fn create_entities(&mut self, _entity_manager: &super::engine::EntityManagerRef) {}
+}
@@ -1,4 +1,21 @@
+pub mod archetype;
+pub mod bundle;
 pub mod camera;
+pub mod change_detection;
+pub mod commands;
 pub mod components;
 pub mod engine;
+pub mod events;
+pub mod hierarchy;
+pub mod input;
+pub mod level_manager;
+pub mod paged_vec;
+pub mod prefab;
+pub mod query;
+pub mod relations;
+pub mod resources;
+pub mod schedule;
+pub mod snapshot;
 pub mod system;
+pub mod task_pool;
+pub mod time;
@@ -0,0 +1,99 @@
+use std::ops::{Index, IndexMut};
+
+pub const PAGE_SIZE: usize = 1024;
+
+/// Vector that grows one fixed size page at a time.
+/// Elements never move once pushed, growing only reallocates the list of pages
+#[derive(Debug, Clone)]
+pub struct PagedVec<T> {
+    pages: Vec<Vec<T>>,
+    len: usize,
+}
+
+impl<T> PagedVec<T> {
+    pub fn new() -> Self {
+        Self {
+            pages: vec![],
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, value: T) {
+        let page = self.len / PAGE_SIZE;
+        if page == self.pages.len() {
+            self.pages.push(Vec::with_capacity(PAGE_SIZE));
+        }
+
+        self.pages[page].push(value);
+        self.len += 1;
+    }
+
+    /// Grows up to `new_len`, never shrinks
+    pub fn resize_with(&mut self, new_len: usize, mut f: impl FnMut() -> T) {
+        while self.len < new_len {
+            self.push(f());
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+
+        Some(&self.pages[index / PAGE_SIZE][index % PAGE_SIZE])
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index >= self.len {
+            return None;
+        }
+
+        Some(&mut self.pages[index / PAGE_SIZE][index % PAGE_SIZE])
+    }
+}
+
+impl<T> Index<usize> for PagedVec<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.get(index).expect("PagedVec index out of bounds")
+    }
+}
+
+impl<T> IndexMut<usize> for PagedVec<T> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        self.get_mut(index).expect("PagedVec index out of bounds")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PagedVec, PAGE_SIZE};
+
+    #[test]
+    fn growing_never_moves_elements() {
+        let mut values = PagedVec::new();
+        values.push(0);
+        let first = &values[0] as *const usize;
+
+        for i in 1..PAGE_SIZE * 3 + 1 {
+            values.push(i);
+        }
+        let last_of_first_page = &values[PAGE_SIZE - 1] as *const usize;
+        values.resize_with(PAGE_SIZE * 8, || 0);
+
+        assert_eq!(&values[0] as *const usize, first);
+        assert_eq!(&values[PAGE_SIZE - 1] as *const usize, last_of_first_page);
+        assert_eq!(values.len(), PAGE_SIZE * 8);
+        assert_eq!(values[PAGE_SIZE * 3], PAGE_SIZE * 3);
+        assert_eq!(values.get(PAGE_SIZE * 8), None);
+    }
+}
@@ -0,0 +1,471 @@
+use std::{any::TypeId, collections::HashMap, fmt, fs, io, path::Path, sync::Arc};
+
+use nalgebra::{UnitQuaternion, Vector3};
+
+use super::{
+    components::{Component, TransformComponent},
+    engine::{EntityError, EntityID, EntityManager},
+};
+
+/// Type erased component value that gets copied onto every instance of a prefab.
+/// `Name` isn't `Clone` and can't be one, names are given per instance with `Overrides::with_name`
+pub trait PrefabComponent: Send + Sync {
+    fn component_type(&self) -> TypeId;
+    fn insert_into(
+        &self,
+        entities: &mut EntityManager,
+        entity: EntityID,
+    ) -> Result<(), EntityError>;
+}
+
+impl<T: Component + Clone> PrefabComponent for T {
+    fn component_type(&self) -> TypeId {
+        TypeId::of::<T>()
+    }
+
+    fn insert_into(
+        &self,
+        entities: &mut EntityManager,
+        entity: EntityID,
+    ) -> Result<(), EntityError> {
+        entities.insert_component(entity, self.clone()).map(|_| ())
+    }
+}
+
+/// Named set of component values that can be spawned any number of times.
+/// Child prefabs are spawned as their own entities, parented to the instance
+#[derive(Clone)]
+pub struct Prefab {
+    name: String,
+    components: Vec<Arc<dyn PrefabComponent>>,
+    children: Vec<Arc<Prefab>>,
+}
+
+impl Prefab {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            components: vec![],
+            children: vec![],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Replaces the value set earlier for the same component type
+    pub fn with<T: Component + Clone>(mut self, component: T) -> Self {
+        self.set_component(Arc::new(component));
+        self
+    }
+
+    pub fn with_child(mut self, child: Arc<Prefab>) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    pub fn children(&self) -> &[Arc<Prefab>] {
+        &self.children
+    }
+
+    fn set_component(&mut self, component: Arc<dyn PrefabComponent>) {
+        self.components
+            .retain(|current| current.component_type() != component.component_type());
+        self.components.push(component);
+    }
+}
+
+impl fmt::Debug for Prefab {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Prefab")
+            .field("name", &self.name)
+            .field("components", &self.components.len())
+            .field("children", &self.children)
+            .finish()
+    }
+}
+
+/// Prefabs registered by name, a resource of the world once the first one is added.
+/// See `EntityManager::add_prefab`
+#[derive(Debug, Default, Clone)]
+pub struct Prefabs {
+    prefabs: HashMap<String, Arc<Prefab>>,
+}
+
+impl Prefabs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the prefab registered under the same name before
+    pub fn insert(&mut self, prefab: Arc<Prefab>) -> Option<Arc<Prefab>> {
+        self.prefabs.insert(prefab.name().to_string(), prefab)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Prefab>> {
+        self.prefabs.get(name).cloned()
+    }
+}
+
+/// Components set on a single instance, on top of the prefab's own
+#[derive(Default)]
+pub struct Overrides {
+    components: Vec<Box<dyn PrefabComponent>>,
+    name: Option<String>,
+}
+
+impl Overrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<T: Component + Clone>(mut self, component: T) -> Self {
+        self.components.push(Box::new(component));
+        self
+    }
+
+    /// Names the instance through `EntityManager::set_name`, the spawn fails if the name is taken
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    fn contains(&self, ty: TypeId) -> bool {
+        self.components
+            .iter()
+            .any(|component| component.component_type() == ty)
+    }
+}
+
+impl EntityManager {
+    pub fn spawn_prefab(&mut self, prefab: &Prefab) -> Result<EntityID, EntityError> {
+        self.spawn_prefab_with(prefab, Overrides::default())
+    }
+
+    /// Spawns the prefab and its children. Nothing is left behind if any of it fails
+    pub fn spawn_prefab_with(
+        &mut self,
+        prefab: &Prefab,
+        overrides: Overrides,
+    ) -> Result<EntityID, EntityError> {
+        let entity = self.try_create_entity()?;
+
+        if let Err(error) = self.build_prefab(entity, prefab, overrides) {
+            self.destroy_entity_recursive(entity);
+            return Err(error);
+        }
+
+        Ok(entity)
+    }
+
+    fn build_prefab(
+        &mut self,
+        entity: EntityID,
+        prefab: &Prefab,
+        overrides: Overrides,
+    ) -> Result<(), EntityError> {
+        for component in prefab.components.iter() {
+            if !overrides.contains(component.component_type()) {
+                component.insert_into(self, entity)?;
+            }
+        }
+
+        for component in overrides.components.iter() {
+            component.insert_into(self, entity)?;
+        }
+
+        if let Some(name) = overrides.name {
+            self.set_name(entity, name)?;
+        }
+
+        for child in prefab.children.iter() {
+            let child = self.spawn_prefab(child)?;
+            self.set_parent(child, entity)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum PrefabError {
+    Io(io::Error),
+    /// The file has no `name` line
+    MissingName,
+    UnknownDirective {
+        line: usize,
+    },
+    /// No parser was registered for the component, see `PrefabLoader::register`
+    UnknownComponent {
+        line: usize,
+        name: String,
+    },
+    InvalidComponent {
+        line: usize,
+        name: String,
+    },
+    /// Child prefabs have to be loaded before the prefabs using them
+    UnknownPrefab {
+        line: usize,
+        name: String,
+    },
+}
+
+impl fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrefabError::Io(error) => write!(f, "Couldn't read the prefab: {}", error),
+            PrefabError::MissingName => write!(f, "The prefab has no name line"),
+            PrefabError::UnknownDirective { line } => {
+                write!(f, "Line {}: expected name, component or child", line)
+            }
+            PrefabError::UnknownComponent { line, name } => {
+                write!(
+                    f,
+                    "Line {}: no component is registered as \"{}\"",
+                    line, name
+                )
+            }
+            PrefabError::InvalidComponent { line, name } => {
+                write!(f, "Line {}: invalid arguments for {}", line, name)
+            }
+            PrefabError::UnknownPrefab { line, name } => {
+                write!(f, "Line {}: no prefab named \"{}\" is loaded", line, name)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for PrefabError {
+    fn from(error: io::Error) -> Self {
+        PrefabError::Io(error)
+    }
+}
+
+/// Components that can be written in prefab files, one `component` line each.
+/// Arguments are the whitespace separated words after the component name
+pub trait FromPrefabArgs: Sized {
+    fn from_args(args: &[&str]) -> Option<Self>;
+}
+
+/// `x y z`, optionally followed by `roll pitch yaw` in radians and `x y z` scale
+impl FromPrefabArgs for TransformComponent {
+    fn from_args(args: &[&str]) -> Option<Self> {
+        let values = args
+            .iter()
+            .map(|arg| arg.parse::<f32>().ok())
+            .collect::<Option<Vec<_>>>()?;
+
+        let (translation, rotation, scale) = match values.as_slice() {
+            [x, y, z] => (Vector3::new(*x, *y, *z), [0.0; 3], [1.0; 3]),
+            [x, y, z, roll, pitch, yaw] => {
+                (Vector3::new(*x, *y, *z), [*roll, *pitch, *yaw], [1.0; 3])
+            }
+            [x, y, z, roll, pitch, yaw, sx, sy, sz] => (
+                Vector3::new(*x, *y, *z),
+                [*roll, *pitch, *yaw],
+                [*sx, *sy, *sz],
+            ),
+            _ => return None,
+        };
+
+        Some(TransformComponent::new(
+            translation,
+            UnitQuaternion::from_euler_angles(rotation[0], rotation[1], rotation[2]),
+            Vector3::from(scale),
+        ))
+    }
+}
+
+type ParseComponent = fn(&[&str]) -> Option<Arc<dyn PrefabComponent>>;
+
+fn parse_component<T: Component + Clone + FromPrefabArgs>(
+    args: &[&str],
+) -> Option<Arc<dyn PrefabComponent>> {
+    T::from_args(args).map(|component| Arc::new(component) as Arc<dyn PrefabComponent>)
+}
+
+/// Reads prefab files:
+/// ```text
+/// # Lines starting with a hash are comments
+/// name enemy
+/// component Transform 0 1 0
+/// child enemy_gun
+/// ```
+#[derive(Debug)]
+pub struct PrefabLoader {
+    parsers: HashMap<String, ParseComponent>,
+}
+
+impl PrefabLoader {
+    pub fn new() -> Self {
+        let mut loader = Self {
+            parsers: HashMap::new(),
+        };
+
+        loader.register::<TransformComponent>("Transform");
+        loader
+    }
+
+    /// Lets prefab files use `T` under `name`
+    pub fn register<T: Component + Clone + FromPrefabArgs>(&mut self, name: &str) {
+        self.parsers
+            .insert(name.to_string(), parse_component::<T> as ParseComponent);
+    }
+
+    /// `prefabs` resolves the names used by `child` lines
+    pub fn parse(
+        &self,
+        source: &str,
+        prefabs: impl Fn(&str) -> Option<Arc<Prefab>>,
+    ) -> Result<Prefab, PrefabError> {
+        let mut name = None;
+        let mut components = vec![];
+        let mut children = vec![];
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let words = line.split_whitespace().collect::<Vec<_>>();
+
+            match words.as_slice() {
+                [] => continue,
+                [comment, ..] if comment.starts_with('#') => continue,
+                ["name", prefab_name] => name = Some(prefab_name.to_string()),
+                ["component", component, args @ ..] => {
+                    let parse = self.parsers.get(*component).ok_or_else(|| {
+                        PrefabError::UnknownComponent {
+                            line: line_number,
+                            name: component.to_string(),
+                        }
+                    })?;
+
+                    components.push(parse(args).ok_or_else(|| PrefabError::InvalidComponent {
+                        line: line_number,
+                        name: component.to_string(),
+                    })?);
+                }
+                ["child", child] => {
+                    children.push(prefabs(child).ok_or_else(|| PrefabError::UnknownPrefab {
+                        line: line_number,
+                        name: child.to_string(),
+                    })?)
+                }
+                _ => return Err(PrefabError::UnknownDirective { line: line_number }),
+            }
+        }
+
+        let mut prefab = Prefab::new(name.ok_or(PrefabError::MissingName)?);
+        for component in components {
+            prefab.set_component(component);
+        }
+        prefab.children = children;
+
+        Ok(prefab)
+    }
+
+    pub fn load(
+        &self,
+        path: impl AsRef<Path>,
+        prefabs: impl Fn(&str) -> Option<Arc<Prefab>>,
+    ) -> Result<Prefab, PrefabError> {
+        let source = fs::read_to_string(path)?;
+        self.parse(&source, prefabs)
+    }
+}
+
+impl Default for PrefabLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Overrides, Prefab, PrefabError, PrefabLoader};
+    use crate::core::{
+        components::TransformComponent,
+        engine::{EntityError, EntityManager},
+    };
+
+    fn no_prefabs(_: &str) -> Option<Arc<Prefab>> {
+        None
+    }
+
+    #[test]
+    fn parses_components_and_children() {
+        let loader = PrefabLoader::new();
+        let gun = Arc::new(loader.parse("name gun", no_prefabs).unwrap());
+
+        let source =
+            "# An enemy holding a gun\n\nname enemy\ncomponent Transform 0 1 0\nchild gun\n";
+        let enemy = loader
+            .parse(source, |name| (name == "gun").then(|| gun.clone()))
+            .unwrap();
+
+        assert_eq!(enemy.name(), "enemy");
+        assert_eq!(enemy.children().len(), 1);
+        assert_eq!(enemy.children()[0].name(), "gun");
+
+        let mut entities = EntityManager::new();
+        let entity = entities.spawn_prefab(&enemy).unwrap();
+        let transform = entities
+            .get_component::<TransformComponent>(entity)
+            .unwrap()
+            .translation;
+        assert_eq!(transform.y, 1.0);
+        assert_eq!(entities.children(entity).len(), 1);
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        let loader = PrefabLoader::new();
+        let parse = |source| loader.parse(source, no_prefabs).unwrap_err();
+
+        assert!(matches!(
+            parse("name a\ncomponent Health 10"),
+            PrefabError::UnknownComponent { line: 2, name } if name == "Health"
+        ));
+        assert!(matches!(
+            parse("name a\n\ncomponent Transform 0 one 0"),
+            PrefabError::InvalidComponent { line: 3, name } if name == "Transform"
+        ));
+        assert!(matches!(
+            parse("name a\nchild b"),
+            PrefabError::UnknownPrefab { line: 2, name } if name == "b"
+        ));
+        assert!(matches!(
+            parse("name a\nspawn b"),
+            PrefabError::UnknownDirective { line: 2 }
+        ));
+        assert!(matches!(
+            parse("component Transform 0 0 0"),
+            PrefabError::MissingName
+        ));
+
+        assert_eq!(
+            parse("name a\ncomponent Transform 0").to_string(),
+            "Line 2: invalid arguments for Transform"
+        );
+    }
+
+    #[test]
+    fn instances_are_named_one_by_one() {
+        let prefab = Prefab::new("enemy");
+        let mut entities = EntityManager::new();
+
+        let first = entities
+            .spawn_prefab_with(&prefab, Overrides::new().with_name("first"))
+            .unwrap();
+        assert_eq!(entities.find_by_name("first"), Some(first));
+
+        let count = entities.entities().count();
+        assert_eq!(
+            entities.spawn_prefab_with(&prefab, Overrides::new().with_name("first")),
+            Err(EntityError::NameTaken(first))
+        );
+        assert_eq!(entities.entities().count(), count);
+    }
+}
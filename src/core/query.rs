@@ -0,0 +1,789 @@
+use std::{
+    any::{type_name, TypeId},
+    cell::UnsafeCell,
+    collections::HashMap,
+    marker::PhantomData,
+};
+
+use super::{
+    archetype::Archetype,
+    change_detection::{ComponentTicks, Mut, SystemTicks},
+    components::{Column, Component, ComponentGuard, ComponentsData, StorageType},
+    engine::EntityID,
+    resources::Resource,
+    task_pool::TaskPool,
+};
+
+/// Component types a query or a system reads and writes.
+/// Queries use it to reject aliasing borrows and to take the component locks,
+/// systems declare it so the engine can tell which of them contend.
+#[derive(Debug, Default, Clone)]
+pub struct Access {
+    reads: HashMap<TypeId, &'static str>,
+    writes: HashMap<TypeId, &'static str>,
+}
+
+impl Access {
+    /// Panics if the same query already writes `T`
+    pub fn add_read<T: Component>(&mut self) {
+        if self.writes.contains_key(&TypeId::of::<T>()) {
+            panic!("{} is already borrowed mutably", type_name::<T>());
+        }
+
+        self.reads.insert(TypeId::of::<T>(), type_name::<T>());
+    }
+
+    /// Panics if the same query already borrows `T`
+    pub fn add_write<T: Component>(&mut self) {
+        if self.reads.contains_key(&TypeId::of::<T>())
+            || self.writes.contains_key(&TypeId::of::<T>())
+        {
+            panic!("{} is already borrowed", type_name::<T>());
+        }
+
+        self.writes.insert(TypeId::of::<T>(), type_name::<T>());
+    }
+
+    /// Filters only look at which entities have `T`, a write of `T` covers that
+    pub fn add_filter<T: Component>(&mut self) {
+        if !self.writes.contains_key(&TypeId::of::<T>()) {
+            self.reads.insert(TypeId::of::<T>(), type_name::<T>());
+        }
+    }
+
+    /// Declares that a system reads `T`
+    pub fn read<T: Component>(mut self) -> Self {
+        self.add_filter::<T>();
+        self
+    }
+
+    /// Declares that a system writes `T`
+    pub fn write<T: Component>(mut self) -> Self {
+        self.reads.remove(&TypeId::of::<T>());
+        self.writes.insert(TypeId::of::<T>(), type_name::<T>());
+        self
+    }
+
+    /// Declares that a system reads the resource `T`
+    pub fn read_resource<T: Resource>(mut self) -> Self {
+        let ty = TypeId::of::<ResourceAccess<T>>();
+        if !self.writes.contains_key(&ty) {
+            self.reads.insert(ty, type_name::<T>());
+        }
+        self
+    }
+
+    /// Declares that a system writes the resource `T`
+    pub fn write_resource<T: Resource>(mut self) -> Self {
+        let ty = TypeId::of::<ResourceAccess<T>>();
+        self.reads.remove(&ty);
+        self.writes.insert(ty, type_name::<T>());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reads.is_empty() && self.writes.is_empty()
+    }
+
+    /// Names of the component types one side writes and the other one touches
+    pub fn conflicts(&self, other: &Access) -> Vec<&'static str> {
+        let mut conflicts = self
+            .writes
+            .iter()
+            .filter(|(ty, _)| other.reads.contains_key(ty) || other.writes.contains_key(ty))
+            .chain(
+                other
+                    .writes
+                    .iter()
+                    .filter(|(ty, _)| self.reads.contains_key(ty)),
+            )
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>();
+
+        conflicts.sort();
+        conflicts
+    }
+
+    pub fn is_compatible(&self, other: &Access) -> bool {
+        self.conflicts(other).is_empty()
+    }
+
+    /// Sorted by type so two queries never take the same locks in opposite orders
+    pub(crate) fn lock_order(&self) -> Vec<(TypeId, bool)> {
+        let mut locks = self
+            .reads
+            .keys()
+            .map(|ty| (*ty, false))
+            .chain(self.writes.keys().map(|ty| (*ty, true)))
+            .collect::<Vec<_>>();
+
+        locks.sort();
+        locks
+    }
+}
+
+/// Keeps resources apart from components of the same type, queries never lock it
+struct ResourceAccess<T>(PhantomData<T>);
+
+/// Something that can be fetched for every matching entity of a query.
+///
+/// Implemented for `&T`, `&mut T`, `Option<Q>`, `EntityID` and tuples of those.
+///
+/// # Safety
+/// `fetch` must only be called on entities for which `matches` returned true
+pub unsafe trait WorldQuery {
+    type Item<'w>;
+    /// Resolved once per query
+    type State;
+    /// Resolved once per archetype
+    type Fetch;
+
+    fn access(access: &mut Access);
+
+    /// None when a required column doesn't exist, the query matches nothing in that case
+    fn init_state(components: &ComponentsData) -> Option<Self::State>;
+
+    /// None when no entity of the archetype can match
+    fn set_archetype(state: &Self::State, archetype: &Archetype) -> Option<Self::Fetch>;
+
+    fn matches(fetch: &Self::Fetch, entity: EntityID, row: usize) -> bool;
+
+    /// # Safety
+    /// The entity must match and no other item of the same entity may be alive for `&mut T`
+    unsafe fn fetch<'w>(fetch: &Self::Fetch, entity: EntityID, row: usize) -> Self::Item<'w>;
+}
+
+/// Narrows down a query without fetching anything
+pub trait QueryFilter {
+    type State;
+    type Fetch;
+
+    fn access(access: &mut Access);
+    fn init_state(components: &ComponentsData) -> Option<Self::State>;
+    fn set_archetype(state: &Self::State, archetype: &Archetype) -> Option<Self::Fetch>;
+    fn matches(fetch: &Self::Fetch, entity: EntityID, row: usize) -> bool;
+}
+
+/// Sparse components are looked up by entity, table components by row
+pub enum ComponentFetch<T: Component> {
+    Sparse(*const Column<T>),
+    Table(*const UnsafeCell<T>, *const UnsafeCell<ComponentTicks>),
+}
+
+impl<T: Component> ComponentFetch<T> {
+    /// Sparse components need their column, table components are resolved per archetype
+    pub(crate) fn init_state(components: &ComponentsData) -> Option<Option<*const Column<T>>> {
+        match T::STORAGE {
+            StorageType::Sparse => components
+                .column::<T>()
+                .map(|column| Some(column as *const Column<T>)),
+            StorageType::Table => Some(None),
+        }
+    }
+
+    pub(crate) fn new(state: &Option<*const Column<T>>, archetype: &Archetype) -> Option<Self> {
+        match state {
+            Some(column) => Some(ComponentFetch::Sparse(*column)),
+            None => archetype
+                .column::<T>()
+                .map(|column| ComponentFetch::Table(column.as_ptr(), column.ticks_ptr())),
+        }
+    }
+
+    pub(crate) fn matches(&self, entity: EntityID) -> bool {
+        match self {
+            ComponentFetch::Sparse(column) => unsafe { (**column).contains(entity.id) },
+            ComponentFetch::Table(..) => true,
+        }
+    }
+
+    unsafe fn get<'w>(&self, entity: EntityID, row: usize) -> &'w T {
+        match self {
+            ComponentFetch::Sparse(column) => (**column).get(entity.id).unwrap(),
+            ComponentFetch::Table(data, _) => &*(*data.add(row)).get(),
+        }
+    }
+
+    unsafe fn get_mut<'w>(
+        &self,
+        entity: EntityID,
+        row: usize,
+    ) -> (&'w mut T, &'w mut ComponentTicks) {
+        match self {
+            ComponentFetch::Sparse(column) => (**column).get_unchecked_mut(entity.id).unwrap(),
+            ComponentFetch::Table(data, ticks) => {
+                (&mut *(*data.add(row)).get(), &mut *(*ticks.add(row)).get())
+            }
+        }
+    }
+
+    /// # Safety
+    /// The entity must match and the lock of `T` must be held
+    pub(crate) unsafe fn ticks<'w>(&self, entity: EntityID, row: usize) -> &'w ComponentTicks {
+        match self {
+            ComponentFetch::Sparse(column) => (**column).ticks(entity.id),
+            ComponentFetch::Table(_, ticks) => &*(*ticks.add(row)).get(),
+        }
+    }
+}
+
+unsafe impl<T: Component> WorldQuery for &T {
+    type Item<'w> = &'w T;
+    type State = Option<*const Column<T>>;
+    type Fetch = ComponentFetch<T>;
+
+    fn access(access: &mut Access) {
+        access.add_read::<T>();
+    }
+
+    fn init_state(components: &ComponentsData) -> Option<Self::State> {
+        ComponentFetch::init_state(components)
+    }
+
+    fn set_archetype(state: &Self::State, archetype: &Archetype) -> Option<Self::Fetch> {
+        ComponentFetch::new(state, archetype)
+    }
+
+    fn matches(fetch: &Self::Fetch, entity: EntityID, _row: usize) -> bool {
+        fetch.matches(entity)
+    }
+
+    unsafe fn fetch<'w>(fetch: &Self::Fetch, entity: EntityID, row: usize) -> Self::Item<'w> {
+        fetch.get(entity, row)
+    }
+}
+
+/// Fetches a `Mut<T>` that marks the component changed when written through
+unsafe impl<T: Component> WorldQuery for &mut T {
+    type Item<'w> = Mut<'w, T>;
+    type State = (Option<*const Column<T>>, SystemTicks);
+    type Fetch = (ComponentFetch<T>, SystemTicks);
+
+    fn access(access: &mut Access) {
+        access.add_write::<T>();
+    }
+
+    fn init_state(components: &ComponentsData) -> Option<Self::State> {
+        Some((
+            ComponentFetch::init_state(components)?,
+            SystemTicks::current(components),
+        ))
+    }
+
+    fn set_archetype(state: &Self::State, archetype: &Archetype) -> Option<Self::Fetch> {
+        Some((ComponentFetch::new(&state.0, archetype)?, state.1))
+    }
+
+    fn matches((fetch, _): &Self::Fetch, entity: EntityID, _row: usize) -> bool {
+        fetch.matches(entity)
+    }
+
+    unsafe fn fetch<'w>(
+        (fetch, ticks): &Self::Fetch,
+        entity: EntityID,
+        row: usize,
+    ) -> Self::Item<'w> {
+        let (value, component_ticks) = fetch.get_mut(entity, row);
+        Mut::new(value, component_ticks, *ticks)
+    }
+}
+
+unsafe impl<Q: WorldQuery> WorldQuery for Option<Q> {
+    type Item<'w> = Option<Q::Item<'w>>;
+    type State = Option<Q::State>;
+    type Fetch = Option<Q::Fetch>;
+
+    fn access(access: &mut Access) {
+        Q::access(access);
+    }
+
+    fn init_state(components: &ComponentsData) -> Option<Self::State> {
+        Some(Q::init_state(components))
+    }
+
+    fn set_archetype(state: &Self::State, archetype: &Archetype) -> Option<Self::Fetch> {
+        Some(
+            state
+                .as_ref()
+                .and_then(|state| Q::set_archetype(state, archetype)),
+        )
+    }
+
+    fn matches(_fetch: &Self::Fetch, _entity: EntityID, _row: usize) -> bool {
+        true
+    }
+
+    unsafe fn fetch<'w>(fetch: &Self::Fetch, entity: EntityID, row: usize) -> Self::Item<'w> {
+        match fetch {
+            Some(fetch) if Q::matches(fetch, entity, row) => Some(Q::fetch(fetch, entity, row)),
+            _ => None,
+        }
+    }
+}
+
+unsafe impl WorldQuery for EntityID {
+    type Item<'w> = EntityID;
+    type State = ();
+    type Fetch = ();
+
+    fn access(_access: &mut Access) {}
+
+    fn init_state(_components: &ComponentsData) -> Option<Self::State> {
+        Some(())
+    }
+
+    fn set_archetype(_state: &Self::State, _archetype: &Archetype) -> Option<Self::Fetch> {
+        Some(())
+    }
+
+    fn matches(_fetch: &Self::Fetch, _entity: EntityID, _row: usize) -> bool {
+        true
+    }
+
+    unsafe fn fetch<'w>(_fetch: &Self::Fetch, entity: EntityID, _row: usize) -> Self::Item<'w> {
+        entity
+    }
+}
+
+/// Only entities that have `T`, without borrowing it
+pub struct With<T>(PhantomData<T>);
+
+/// Only entities that don't have `T`
+pub struct Without<T>(PhantomData<T>);
+
+impl<T: Component> QueryFilter for With<T> {
+    type State = Option<*const Column<T>>;
+    type Fetch = ComponentFetch<T>;
+
+    fn access(access: &mut Access) {
+        access.add_filter::<T>();
+    }
+
+    fn init_state(components: &ComponentsData) -> Option<Self::State> {
+        ComponentFetch::init_state(components)
+    }
+
+    fn set_archetype(state: &Self::State, archetype: &Archetype) -> Option<Self::Fetch> {
+        ComponentFetch::new(state, archetype)
+    }
+
+    fn matches(fetch: &Self::Fetch, entity: EntityID, _row: usize) -> bool {
+        fetch.matches(entity)
+    }
+}
+
+impl<T: Component> QueryFilter for Without<T> {
+    type State = Option<*const Column<T>>;
+    type Fetch = Option<*const Column<T>>;
+
+    fn access(access: &mut Access) {
+        access.add_filter::<T>();
+    }
+
+    fn init_state(components: &ComponentsData) -> Option<Self::State> {
+        Some(
+            components
+                .column::<T>()
+                .map(|column| column as *const Column<T>),
+        )
+    }
+
+    fn set_archetype(state: &Self::State, archetype: &Archetype) -> Option<Self::Fetch> {
+        match T::STORAGE {
+            StorageType::Table if archetype.contains::<T>() => None,
+            _ => Some(*state),
+        }
+    }
+
+    fn matches(fetch: &Self::Fetch, entity: EntityID, _row: usize) -> bool {
+        match fetch {
+            Some(column) => unsafe { !(**column).contains(entity.id) },
+            None => true,
+        }
+    }
+}
+
+impl QueryFilter for () {
+    type State = ();
+    type Fetch = ();
+
+    fn access(_access: &mut Access) {}
+
+    fn init_state(_components: &ComponentsData) -> Option<Self::State> {
+        Some(())
+    }
+
+    fn set_archetype(_state: &Self::State, _archetype: &Archetype) -> Option<Self::Fetch> {
+        Some(())
+    }
+
+    fn matches(_fetch: &Self::Fetch, _entity: EntityID, _row: usize) -> bool {
+        true
+    }
+}
+
+macro_rules! impl_query_tuple {
+    ($($name:ident),*) => {
+        #[allow(non_snake_case)]
+        unsafe impl<$($name: WorldQuery),*> WorldQuery for ($($name,)*) {
+            type Item<'w> = ($($name::Item<'w>,)*);
+            type State = ($($name::State,)*);
+            type Fetch = ($($name::Fetch,)*);
+
+            fn access(access: &mut Access) {
+                $($name::access(access);)*
+            }
+
+            fn init_state(components: &ComponentsData) -> Option<Self::State> {
+                Some(($($name::init_state(components)?,)*))
+            }
+
+            fn set_archetype(state: &Self::State, archetype: &Archetype) -> Option<Self::Fetch> {
+                let ($($name,)*) = state;
+                Some(($($name::set_archetype($name, archetype)?,)*))
+            }
+
+            fn matches(fetch: &Self::Fetch, entity: EntityID, row: usize) -> bool {
+                let ($($name,)*) = fetch;
+                true $(&& $name::matches($name, entity, row))*
+            }
+
+            unsafe fn fetch<'w>(fetch: &Self::Fetch, entity: EntityID, row: usize) -> Self::Item<'w> {
+                let ($($name,)*) = fetch;
+                ($($name::fetch($name, entity, row),)*)
+            }
+        }
+
+        #[allow(non_snake_case)]
+        impl<$($name: QueryFilter),*> QueryFilter for ($($name,)*) {
+            type State = ($($name::State,)*);
+            type Fetch = ($($name::Fetch,)*);
+
+            fn access(access: &mut Access) {
+                $($name::access(access);)*
+            }
+
+            fn init_state(components: &ComponentsData) -> Option<Self::State> {
+                Some(($($name::init_state(components)?,)*))
+            }
+
+            fn set_archetype(state: &Self::State, archetype: &Archetype) -> Option<Self::Fetch> {
+                let ($($name,)*) = state;
+                Some(($($name::set_archetype($name, archetype)?,)*))
+            }
+
+            fn matches(fetch: &Self::Fetch, entity: EntityID, row: usize) -> bool {
+                let ($($name,)*) = fetch;
+                true $(&& $name::matches($name, entity, row))*
+            }
+        }
+    };
+}
+
+impl_query_tuple!(A);
+impl_query_tuple!(A, B);
+impl_query_tuple!(A, B, C);
+impl_query_tuple!(A, B, C, D);
+impl_query_tuple!(A, B, C, D, E);
+impl_query_tuple!(A, B, C, D, E, F);
+impl_query_tuple!(A, B, C, D, E, F, G);
+impl_query_tuple!(A, B, C, D, E, F, G, H);
+
+/// Borrow of the components matching `Q` and `F`.
+/// Holds the component locks until dropped, iterate it through `iter`.
+/// Created through `EntityManager::query` and `EntityManager::query_filtered`
+pub struct Query<'w, Q: WorldQuery, F: QueryFilter = ()> {
+    components: &'w ComponentsData,
+    state: Option<(Q::State, F::State)>,
+    _guards: Vec<ComponentGuard<'w>>,
+}
+
+impl<'w, Q: WorldQuery, F: QueryFilter> Query<'w, Q, F> {
+    /// Blocks until every component type in `Q` and `F` can be borrowed
+    pub(crate) fn new(components: &'w ComponentsData) -> Self {
+        let mut access = Access::default();
+        Q::access(&mut access);
+        F::access(&mut access);
+
+        let guards = access
+            .lock_order()
+            .into_iter()
+            .filter_map(|(ty, write)| components.lock(ty, write))
+            .collect();
+
+        let state = match (Q::init_state(components), F::init_state(components)) {
+            (Some(query), Some(filter)) => Some((query, filter)),
+            _ => None,
+        };
+
+        Self {
+            components,
+            state,
+            _guards: guards,
+        }
+    }
+
+    /// Iterates the matching entities one archetype at a time
+    pub fn iter(&mut self) -> QueryIter<'_, Q, F> {
+        QueryIter {
+            state: self.state.as_ref(),
+            archetypes: self.components.tables.archetypes().iter(),
+            current: None,
+            row: 0,
+        }
+    }
+
+    /// Runs `f` for every matching entity, in batches of up to `batch_size` rows spread over the pool.
+    /// Returns once every batch ran. Each entity is visited once so `&mut T` items never alias
+    pub fn par_for_each(
+        &mut self,
+        pool: &TaskPool,
+        batch_size: usize,
+        f: impl Fn(Q::Item<'_>) + Send + Sync,
+    ) {
+        let Some((query, filter)) = self.state.as_ref() else {
+            return;
+        };
+
+        let fetches = SharedFetches::<Q, F>(
+            self.components
+                .tables
+                .archetypes()
+                .iter()
+                .filter(|archetype| archetype.len() > 0)
+                .filter_map(|archetype| {
+                    Some((
+                        Q::set_archetype(query, archetype)?,
+                        F::set_archetype(filter, archetype)?,
+                        archetype.entities(),
+                    ))
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        let (fetches, f) = (&fetches, &f);
+        let batch_size = batch_size.max(1);
+
+        pool.scope(|scope| {
+            for archetype in 0..fetches.len() {
+                for start in (0..fetches.entities(archetype).len()).step_by(batch_size) {
+                    scope.spawn(move |_| {
+                        let (query_fetch, filter_fetch, entities) = fetches.get(archetype);
+                        let end = entities.len().min(start + batch_size);
+
+                        for (row, entity) in
+                            entities.iter().copied().enumerate().take(end).skip(start)
+                        {
+                            if Q::matches(query_fetch, entity, row)
+                                && F::matches(filter_fetch, entity, row)
+                            {
+                                //Batches don't overlap, the row is fetched once
+                                f(unsafe { Q::fetch(query_fetch, entity, row) });
+                            }
+                        }
+                    });
+                }
+            }
+        });
+    }
+
+    /// None if the entity is dead or doesn't match
+    pub fn get(&mut self, entity: EntityID) -> Option<Q::Item<'_>> {
+        let (query, filter) = self.state.as_ref()?;
+        let location = self.components.tables.location(entity.id)?;
+        let archetype = &self.components.tables.archetypes()[location.archetype];
+
+        if archetype.entities()[location.row] != entity {
+            return None;
+        }
+
+        let query_fetch = Q::set_archetype(query, archetype)?;
+        let filter_fetch = F::set_archetype(filter, archetype)?;
+        if !Q::matches(&query_fetch, entity, location.row)
+            || !F::matches(&filter_fetch, entity, location.row)
+        {
+            return None;
+        }
+
+        //The item borrows the query mutably, so it is the only one alive
+        Some(unsafe { Q::fetch(&query_fetch, entity, location.row) })
+    }
+}
+
+/// Fetches hold raw column pointers. The query keeps its locks during `Query::par_for_each`
+/// and every row goes to a single batch, so the batches can share them
+struct SharedFetches<'q, Q: WorldQuery, F: QueryFilter>(Vec<(Q::Fetch, F::Fetch, &'q [EntityID])>);
+
+unsafe impl<Q: WorldQuery, F: QueryFilter> Sync for SharedFetches<'_, Q, F> {}
+
+impl<'q, Q: WorldQuery, F: QueryFilter> SharedFetches<'q, Q, F> {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn entities(&self, archetype: usize) -> &'q [EntityID] {
+        self.0[archetype].2
+    }
+
+    fn get(&self, archetype: usize) -> &(Q::Fetch, F::Fetch, &'q [EntityID]) {
+        &self.0[archetype]
+    }
+}
+
+pub struct QueryIter<'q, Q: WorldQuery, F: QueryFilter> {
+    state: Option<&'q (Q::State, F::State)>,
+    archetypes: std::slice::Iter<'q, Archetype>,
+    current: Option<(Q::Fetch, F::Fetch, &'q [EntityID])>,
+    row: usize,
+}
+
+impl<'q, Q: WorldQuery, F: QueryFilter> Iterator for QueryIter<'q, Q, F> {
+    type Item = Q::Item<'q>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (query, filter) = self.state?;
+
+        loop {
+            if let Some((query_fetch, filter_fetch, entities)) = &self.current {
+                while self.row < entities.len() {
+                    let row = self.row;
+                    self.row += 1;
+
+                    let entity = entities[row];
+                    if Q::matches(query_fetch, entity, row) && F::matches(filter_fetch, entity, row)
+                    {
+                        //Every entity is visited once so mutable items never alias
+                        return Some(unsafe { Q::fetch(query_fetch, entity, row) });
+                    }
+                }
+            }
+
+            let archetype = self.archetypes.next()?;
+            self.row = 0;
+            self.current = None;
+
+            if archetype.len() == 0 {
+                continue;
+            }
+
+            if let (Some(query_fetch), Some(filter_fetch)) = (
+                Q::set_archetype(query, archetype),
+                F::set_archetype(filter, archetype),
+            ) {
+                self.current = Some((query_fetch, filter_fetch, archetype.entities()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Query, With, Without};
+    use crate::core::{
+        components::{Component, StorageType},
+        engine::{EntityID, EntityManager},
+    };
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Health(i32);
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Position(i32);
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Frozen;
+
+    impl Component for Health {}
+    impl Component for Frozen {}
+
+    impl Component for Position {
+        const STORAGE: StorageType = StorageType::Table;
+    }
+
+    /// Entity `i` has a position of `i`, even ones have health and every third one is frozen
+    fn world() -> (EntityManager, Vec<EntityID>) {
+        let mut entities = EntityManager::new();
+        let ids = (0..6)
+            .map(|i| {
+                let entity = entities.create_entity();
+                entities.insert_component(entity, Position(i)).unwrap();
+                if i % 2 == 0 {
+                    entities.insert_component(entity, Health(10 * i)).unwrap();
+                }
+                if i % 3 == 0 {
+                    entities.insert_component(entity, Frozen).unwrap();
+                }
+                entity
+            })
+            .collect();
+        (entities, ids)
+    }
+
+    #[test]
+    fn sparse_and_table_components_together() {
+        let (entities, ids) = world();
+
+        for (mut position, health) in entities.query::<(&mut Position, &Health)>().iter() {
+            position.0 += health.0;
+        }
+
+        let mut query: Query<'_, (EntityID, &Position)> = entities.query();
+        let positions = query
+            .iter()
+            .map(|(entity, position)| (entity, position.0))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            [
+                (ids[0], 0),
+                (ids[1], 1),
+                (ids[2], 22),
+                (ids[3], 3),
+                (ids[4], 44),
+                (ids[5], 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_and_optional_fetches() {
+        let (entities, ids) = world();
+
+        let mut query = entities.query_filtered::<EntityID, (With<Health>, Without<Frozen>)>();
+        assert_eq!(query.iter().collect::<Vec<_>>(), [ids[2], ids[4]]);
+        drop(query);
+
+        let mut query = entities.query::<(&Position, Option<&Health>)>();
+        let healths = query
+            .iter()
+            .map(|(position, health)| (position.0, health.map(|health| health.0)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            healths,
+            [
+                (0, Some(0)),
+                (1, None),
+                (2, Some(20)),
+                (3, None),
+                (4, Some(40)),
+                (5, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn get_ignores_stale_handles() {
+        let (mut entities, ids) = world();
+
+        assert!(entities.destroy_entity(ids[2]));
+        assert!(entities.query::<&Position>().get(ids[2]).is_none());
+
+        //The new entity reuses the slot of the destroyed one
+        let reused = entities.create_entity();
+        assert_eq!(reused.id(), ids[2].id());
+        entities.insert_component(reused, Position(7)).unwrap();
+
+        let mut query = entities.query::<&Position>();
+        assert!(query.get(ids[2]).is_none());
+        assert_eq!(query.get(reused).map(|position| position.0), Some(7));
+        assert!(entities.query::<&Health>().get(ids[1]).is_none());
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+};
+
+use super::engine::EntityID;
+
+/// Marker type naming a relation between two entities
+/// ```ignore
+/// struct Targets;
+/// impl Relation for Targets {}
+///
+/// entities.add_relation::<Targets>(turret, player)?;
+/// ```
+pub trait Relation: Any + Send + Sync {}
+
+/// Edges of a single relation type, indexed from both ends
+#[derive(Debug, Default, Clone)]
+struct RelationEdges {
+    forward: HashMap<EntityID, Vec<EntityID>>,
+    reverse: HashMap<EntityID, Vec<EntityID>>,
+}
+
+impl RelationEdges {
+    fn insert(&mut self, source: EntityID, target: EntityID) -> bool {
+        let targets = self.forward.entry(source).or_default();
+        if targets.contains(&target) {
+            return false;
+        }
+
+        targets.push(target);
+        self.reverse.entry(target).or_default().push(source);
+        true
+    }
+
+    fn remove(&mut self, source: EntityID, target: EntityID) -> bool {
+        let removed = unlink(&mut self.forward, source, target);
+        if removed {
+            unlink(&mut self.reverse, target, source);
+        }
+        removed
+    }
+
+    fn remove_entity(&mut self, entity: EntityID) {
+        for target in self.forward.remove(&entity).unwrap_or_default() {
+            unlink(&mut self.reverse, target, entity);
+        }
+
+        for source in self.reverse.remove(&entity).unwrap_or_default() {
+            unlink(&mut self.forward, source, entity);
+        }
+    }
+}
+
+/// Removes `to` from the edges of `from`, dropping the entry once it is empty
+fn unlink(edges: &mut HashMap<EntityID, Vec<EntityID>>, from: EntityID, to: EntityID) -> bool {
+    let Some(linked) = edges.get_mut(&from) else {
+        return false;
+    };
+
+    let len = linked.len();
+    linked.retain(|entity| *entity != to);
+    let removed = linked.len() != len;
+
+    if linked.is_empty() {
+        edges.remove(&from);
+    }
+    removed
+}
+
+/// `(source, relation type, target)` triples. Every relation type is stored
+/// forward and reverse so both "what does it target" and "what targets it" are lookups
+#[derive(Debug, Default, Clone)]
+pub struct Relations {
+    relations: HashMap<TypeId, RelationEdges>,
+}
+
+impl Relations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns false if the relation already existed
+    pub fn insert<R: Relation>(&mut self, source: EntityID, target: EntityID) -> bool {
+        self.relations
+            .entry(TypeId::of::<R>())
+            .or_default()
+            .insert(source, target)
+    }
+
+    /// Returns false if there was no such relation
+    pub fn remove<R: Relation>(&mut self, source: EntityID, target: EntityID) -> bool {
+        self.relations
+            .get_mut(&TypeId::of::<R>())
+            .is_some_and(|edges| edges.remove(source, target))
+    }
+
+    pub fn contains<R: Relation>(&self, source: EntityID, target: EntityID) -> bool {
+        self.targets::<R>(source).contains(&target)
+    }
+
+    /// Entities `source` relates to, in the order the relations were added
+    pub fn targets<R: Relation>(&self, source: EntityID) -> &[EntityID] {
+        self.edges::<R>()
+            .and_then(|edges| edges.forward.get(&source))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Entities that relate to `target`
+    pub fn sources<R: Relation>(&self, target: EntityID) -> &[EntityID] {
+        self.edges::<R>()
+            .and_then(|edges| edges.reverse.get(&target))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Every `(source, target)` pair of the relation
+    pub fn iter<R: Relation>(&self) -> impl Iterator<Item = (EntityID, EntityID)> + '_ {
+        self.edges::<R>()
+            .into_iter()
+            .flat_map(|edges| edges.forward.iter())
+            .flat_map(|(source, targets)| targets.iter().map(|target| (*source, *target)))
+    }
+
+    /// Drops every relation from or to the entity, of every relation type
+    pub(crate) fn remove_entity(&mut self, entity: EntityID) {
+        for edges in self.relations.values_mut() {
+            edges.remove_entity(entity);
+        }
+    }
+
+    fn edges<R: Relation>(&self) -> Option<&RelationEdges> {
+        self.relations.get(&TypeId::of::<R>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+
+    use super::{Relation, Relations};
+    use crate::core::engine::EntityManager;
+
+    struct Targets;
+    struct Follows;
+
+    impl Relation for Targets {}
+    impl Relation for Follows {}
+
+    /// Both maps of `R` are empty
+    fn is_empty<R: Relation>(relations: &Relations) -> bool {
+        relations
+            .relations
+            .get(&TypeId::of::<R>())
+            .is_none_or(|edges| edges.forward.is_empty() && edges.reverse.is_empty())
+    }
+
+    #[test]
+    fn lookups_go_both_ways() {
+        let mut entities = EntityManager::new();
+        let [turret, other, player, enemy] = [(); 4].map(|_| entities.create_entity());
+
+        assert!(entities.add_relation::<Targets>(turret, player).unwrap());
+        assert!(entities.add_relation::<Targets>(turret, enemy).unwrap());
+        assert!(entities.add_relation::<Targets>(other, player).unwrap());
+        assert!(!entities.add_relation::<Targets>(turret, player).unwrap());
+
+        assert_eq!(
+            entities.relation_targets::<Targets>(turret),
+            [player, enemy]
+        );
+        assert_eq!(
+            entities.relation_sources::<Targets>(player),
+            [turret, other]
+        );
+        assert!(entities.has_relation::<Targets>(turret, enemy));
+        assert!(!entities.has_relation::<Targets>(enemy, turret));
+    }
+
+    #[test]
+    fn removed_relations_leave_both_maps() {
+        let mut entities = EntityManager::new();
+        let [turret, player] = [(); 2].map(|_| entities.create_entity());
+        entities.add_relation::<Targets>(turret, player).unwrap();
+
+        assert!(entities.remove_relation::<Targets>(turret, player));
+        assert!(!entities.remove_relation::<Targets>(turret, player));
+        assert!(entities.relation_targets::<Targets>(turret).is_empty());
+        assert!(entities.relation_sources::<Targets>(player).is_empty());
+        assert!(is_empty::<Targets>(entities.relations()));
+    }
+
+    #[test]
+    fn despawning_either_end_drops_the_relation() {
+        for despawn_source in [true, false] {
+            let mut entities = EntityManager::new();
+            let [turret, player] = [(); 2].map(|_| entities.create_entity());
+            entities.add_relation::<Targets>(turret, player).unwrap();
+            entities.add_relation::<Follows>(player, turret).unwrap();
+
+            let (despawned, kept) = match despawn_source {
+                true => (turret, player),
+                false => (player, turret),
+            };
+            assert!(entities.destroy_entity(despawned));
+
+            assert!(entities.relation_targets::<Targets>(kept).is_empty());
+            assert!(entities.relation_sources::<Targets>(kept).is_empty());
+            assert!(is_empty::<Targets>(entities.relations()));
+            assert!(is_empty::<Follows>(entities.relations()));
+        }
+    }
+
+    #[test]
+    fn relation_types_are_separate() {
+        let mut entities = EntityManager::new();
+        let [a, b, c] = [(); 3].map(|_| entities.create_entity());
+        entities.add_relation::<Targets>(a, b).unwrap();
+        entities.add_relation::<Follows>(a, c).unwrap();
+
+        assert_eq!(entities.relation_targets::<Targets>(a), [b]);
+        assert_eq!(entities.relation_targets::<Follows>(a), [c]);
+        assert!(!entities.has_relation::<Follows>(a, b));
+
+        entities.remove_relation::<Follows>(a, c);
+        assert_eq!(entities.relation_targets::<Targets>(a), [b]);
+        assert_eq!(
+            entities.relations().iter::<Targets>().collect::<Vec<_>>(),
+            [(a, b)]
+        );
+    }
+}
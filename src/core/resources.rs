@@ -0,0 +1,173 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use super::components::HeldLock;
+
+/// Global state that isn't attached to an entity, one value per type
+pub trait Resource: Any + Send + Sync {}
+
+impl<T: Any + Send + Sync> Resource for T {}
+
+/// Shared borrow of a resource, see `Resources::get`
+pub struct Res<'a, T> {
+    guard: RwLockReadGuard<'a, T>,
+    _held: HeldLock,
+}
+
+impl<T> Deref for Res<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+/// Exclusive borrow of a resource, see `Resources::get_mut`
+pub struct ResMut<'a, T> {
+    guard: RwLockWriteGuard<'a, T>,
+    _held: HeldLock,
+}
+
+impl<T> Deref for ResMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for ResMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+/// Typed resource map. Like components, every resource type has its own lock
+/// so systems using different resources don't wait on each other,
+/// and borrowing a resource the thread already holds panics instead of deadlocking.
+/// Inserting and removing need `&mut`
+#[derive(Default)]
+pub struct Resources {
+    resources: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Resources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the resource it replaced
+    pub fn insert<T: Resource>(&mut self, resource: T) -> Option<T> {
+        match self.lock_mut::<T>() {
+            Some(current) => Some(std::mem::replace(current, resource)),
+            None => {
+                self.resources
+                    .insert(TypeId::of::<T>(), Box::new(RwLock::new(resource)));
+                None
+            }
+        }
+    }
+
+    pub fn remove<T: Resource>(&mut self) -> Option<T> {
+        self.resources
+            .remove(&TypeId::of::<T>())
+            .and_then(|resource| resource.downcast::<RwLock<T>>().ok())
+            .map(|resource| {
+                resource
+                    .into_inner()
+                    .unwrap_or_else(PoisonError::into_inner)
+            })
+    }
+
+    pub fn contains<T: Resource>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Blocks while another thread holds `T` for writing.
+    /// Panics if this thread already holds `T`
+    pub fn get<T: Resource>(&self) -> Option<Res<'_, T>> {
+        let lock = self.lock::<T>()?;
+        let held = self.hold::<T>();
+        Some(Res {
+            guard: lock.read().unwrap_or_else(PoisonError::into_inner),
+            _held: held,
+        })
+    }
+
+    /// Blocks while another thread holds `T`. Panics if this thread already holds it
+    pub fn get_mut<T: Resource>(&self) -> Option<ResMut<'_, T>> {
+        let lock = self.lock::<T>()?;
+        let held = self.hold::<T>();
+        Some(ResMut {
+            guard: lock.write().unwrap_or_else(PoisonError::into_inner),
+            _held: held,
+        })
+    }
+
+    fn hold<T: Resource>(&self) -> HeldLock {
+        HeldLock::new(
+            self as *const Self as usize,
+            TypeId::of::<T>(),
+            type_name::<T>(),
+        )
+    }
+
+    fn lock<T: Resource>(&self) -> Option<&RwLock<T>> {
+        self.resources
+            .get(&TypeId::of::<T>())
+            .and_then(|resource| resource.downcast_ref::<RwLock<T>>())
+    }
+
+    fn lock_mut<T: Resource>(&mut self) -> Option<&mut T> {
+        self.resources
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|resource| resource.downcast_mut::<RwLock<T>>())
+            .map(|lock| lock.get_mut().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resources;
+
+    #[derive(Debug, PartialEq)]
+    struct Score(u32);
+    #[derive(Debug, PartialEq)]
+    struct Level(u32);
+
+    #[test]
+    fn insert_replace_and_remove() {
+        let mut resources = Resources::new();
+        assert_eq!(resources.insert(Score(1)), None);
+        assert_eq!(resources.insert(Score(2)), Some(Score(1)));
+        resources.insert(Level(1));
+
+        resources.get_mut::<Score>().unwrap().0 += 1;
+        assert_eq!(*resources.get::<Score>().unwrap(), Score(3));
+
+        //Different types are borrowed independently
+        let level = resources.get_mut::<Level>().unwrap();
+        let score = resources.get::<Score>().unwrap();
+        assert_eq!((level.0, score.0), (1, 3));
+        drop((level, score));
+
+        assert_eq!(resources.remove::<Score>(), Some(Score(3)));
+        assert!(!resources.contains::<Score>());
+        assert!(resources.get::<Score>().is_none());
+        assert!(resources.contains::<Level>());
+    }
+
+    #[test]
+    #[should_panic(expected = "Score is already locked by this thread")]
+    fn borrowing_a_held_resource_panics() {
+        let mut resources = Resources::new();
+        resources.insert(Score(1));
+
+        let _score = resources.get::<Score>().unwrap();
+        resources.get_mut::<Score>();
+    }
+}
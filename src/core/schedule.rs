@@ -0,0 +1,298 @@
+use std::{collections::HashMap, fmt};
+
+use super::{engine::WorldId, system::System};
+
+/// Part of the frame a system runs in, see `SystemTrait::stage`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Stage {
+    /// Physics and gameplay. Runs zero or more times a frame, once per fixed timestep
+    /// of wall clock time, before the variable stage
+    Fixed,
+    /// Rendering and input. Runs once a frame, `Time::alpha` tells how far it is
+    /// between two fixed steps
+    #[default]
+    Variable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// The system is ordered against a label no system has
+    UnknownLabel { system: String, label: &'static str },
+    /// Names of the systems in the cycle, the first one repeated at the end
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleError::UnknownLabel { system, label } => write!(
+                f,
+                "{} is ordered against \"{}\" but no system of its stage has that label",
+                system, label
+            ),
+            ScheduleError::Cycle(systems) => {
+                write!(f, "Systems wait on each other: {}", systems.join(" -> "))
+            }
+        }
+    }
+}
+
+/// Order the systems of a stage run in, from `SystemTrait::labels`, `before` and `after`.
+/// Systems are refered to by their index in the list the schedule was built from
+#[derive(Debug)]
+pub struct Schedule {
+    /// Every system comes after the systems it depends on
+    order: Vec<usize>,
+    /// Systems that have to finish before the system starts
+    dependencies: Vec<Vec<usize>>,
+    /// `ordered[a][b]` when `a` always finishes before `b` starts, directly or not
+    ordered: Vec<Vec<bool>>,
+}
+
+impl Schedule {
+    pub fn new(systems: &[(WorldId, System)]) -> Result<Self, ScheduleError> {
+        let mut labels: HashMap<&'static str, Vec<usize>> = HashMap::new();
+        for (index, (_, system)) in systems.iter().enumerate() {
+            for label in system.labels() {
+                labels.entry(label).or_default().push(index);
+            }
+        }
+
+        let labelled = |system: &System, label: &'static str| {
+            labels
+                .get(label)
+                .ok_or_else(|| ScheduleError::UnknownLabel {
+                    system: system.name(),
+                    label,
+                })
+        };
+
+        let mut dependencies = vec![vec![]; systems.len()];
+        for (index, (_, system)) in systems.iter().enumerate() {
+            for label in system.after() {
+                dependencies[index].extend(labelled(system, label)?);
+            }
+
+            for label in system.before() {
+                for dependent in labelled(system, label)? {
+                    dependencies[*dependent].push(index);
+                }
+            }
+        }
+
+        for dependencies in dependencies.iter_mut() {
+            dependencies.sort();
+            dependencies.dedup();
+        }
+
+        let order = topological_order(&dependencies).map_err(|cycle| {
+            ScheduleError::Cycle(cycle.iter().map(|index| systems[*index].1.name()).collect())
+        })?;
+
+        let mut ordered = vec![vec![false; systems.len()]; systems.len()];
+        for system in order.iter().copied() {
+            for dependency in dependencies[system].iter().copied() {
+                ordered[dependency][system] = true;
+                for before in ordered.iter_mut() {
+                    if before[dependency] {
+                        before[system] = true;
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            order,
+            dependencies,
+            ordered,
+        })
+    }
+
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    pub fn dependencies(&self, system: usize) -> &[usize] {
+        &self.dependencies[system]
+    }
+
+    /// True if one of the systems always runs before the other, they can't run at the same time
+    pub fn is_ordered(&self, a: usize, b: usize) -> bool {
+        self.ordered[a][b] || self.ordered[b][a]
+    }
+}
+
+/// Kahn's algorithm. Returns one of the cycles if there are any
+fn topological_order(dependencies: &[Vec<usize>]) -> Result<Vec<usize>, Vec<usize>> {
+    let mut remaining = dependencies.iter().map(Vec::len).collect::<Vec<_>>();
+    let mut dependents = vec![vec![]; dependencies.len()];
+    for (system, dependencies) in dependencies.iter().enumerate() {
+        for dependency in dependencies {
+            dependents[*dependency].push(system);
+        }
+    }
+
+    //Ties keep the order the systems were added in
+    let mut ready = (0..dependencies.len())
+        .filter(|system| remaining[*system] == 0)
+        .collect::<Vec<_>>();
+    ready.reverse();
+
+    let mut order = Vec::with_capacity(dependencies.len());
+    while let Some(system) = ready.pop() {
+        order.push(system);
+
+        let mut unblocked = vec![];
+        for dependent in dependents[system].iter().copied() {
+            remaining[dependent] -= 1;
+            if remaining[dependent] == 0 {
+                unblocked.push(dependent);
+            }
+        }
+
+        ready.extend(unblocked.into_iter().rev());
+    }
+
+    if order.len() == dependencies.len() {
+        return Ok(order);
+    }
+
+    //Every system left waits on another one left, walking the dependencies has to loop
+    let mut path = vec![(0..dependencies.len())
+        .find(|system| remaining[*system] > 0)
+        .unwrap()];
+    loop {
+        let current = *path.last().unwrap();
+        let next = dependencies[current]
+            .iter()
+            .copied()
+            .find(|dependency| remaining[*dependency] > 0)
+            .unwrap();
+
+        if let Some(start) = path.iter().position(|system| *system == next) {
+            let mut cycle = path.split_off(start);
+            cycle.reverse();
+            cycle.insert(0, next);
+            return Err(cycle);
+        }
+        path.push(next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Schedule, ScheduleError};
+    use crate::core::{
+        commands::Commands,
+        components::Component,
+        engine::{Engine, EntityManagerRef, SystemEvent, WorldId},
+        query::Access,
+        system::{find_conflicts, SysResult, System, SystemTrait},
+    };
+
+    struct Position;
+
+    impl Component for Position {}
+
+    /// Labelled with its own name, writes `Position`
+    struct Labelled {
+        name: &'static str,
+        after: Vec<&'static str>,
+    }
+
+    impl SystemTrait for Labelled {
+        fn access(&self) -> Access {
+            Access::default().write::<Position>()
+        }
+
+        fn step(
+            &mut self,
+            _time: usize,
+            _entities: &EntityManagerRef,
+            _commands: &mut Commands,
+            _engine: &Engine,
+        ) -> SysResult<Vec<SystemEvent>> {
+            Ok(vec![])
+        }
+
+        fn name(&self) -> String {
+            self.name.to_string()
+        }
+
+        fn labels(&self) -> Vec<&'static str> {
+            vec![self.name]
+        }
+
+        fn after(&self) -> Vec<&'static str> {
+            self.after.clone()
+        }
+    }
+
+    fn systems(systems: &[(&'static str, &[&'static str])]) -> Vec<(WorldId, System)> {
+        systems
+            .iter()
+            .map(|(name, after)| {
+                let system = Labelled {
+                    name,
+                    after: after.to_vec(),
+                };
+                (WorldId::MAIN, SystemTrait::into_system(system))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cycles_name_their_systems() {
+        let error = Schedule::new(&systems(&[("a", &["b"]), ("b", &["a"])])).unwrap_err();
+
+        let ScheduleError::Cycle(cycle) = &error else {
+            panic!("expected a cycle, got {:?}", error);
+        };
+        assert_eq!(cycle.len(), 3);
+        assert_eq!(cycle[0], cycle[2]);
+        assert!(cycle.contains(&"a".to_string()));
+        assert!(cycle.contains(&"b".to_string()));
+        assert_eq!(
+            error.to_string(),
+            format!("Systems wait on each other: {}", cycle.join(" -> "))
+        );
+    }
+
+    #[test]
+    fn unknown_labels_are_reported() {
+        let error = Schedule::new(&systems(&[("a", &["missing"])])).unwrap_err();
+
+        assert_eq!(
+            error,
+            ScheduleError::UnknownLabel {
+                system: "a".to_string(),
+                label: "missing",
+            }
+        );
+    }
+
+    #[test]
+    fn indirect_ordering_prevents_conflicts() {
+        let systems = systems(&[("c", &["b"]), ("a", &[]), ("b", &["a"]), ("d", &[])]);
+        let schedule = Schedule::new(&systems).unwrap();
+
+        assert_eq!(schedule.order(), [1, 2, 0, 3]);
+        //a only comes before c through b
+        assert!(schedule.is_ordered(1, 0));
+        assert!(!schedule.is_ordered(0, 3));
+
+        let mut conflicts = find_conflicts(&systems, &schedule)
+            .into_iter()
+            .map(|conflict| conflict.systems)
+            .collect::<Vec<_>>();
+        conflicts.sort();
+        assert_eq!(
+            conflicts,
+            [
+                ("a".to_string(), "d".to_string()),
+                ("b".to_string(), "d".to_string()),
+                ("c".to_string(), "d".to_string()),
+            ]
+        );
+    }
+}
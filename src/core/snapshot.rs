@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+
+use super::engine::{EntityID, EntityManager};
+
+/// Copy of the entities, components, names and relations of an `EntityManager`,
+/// taken with `EntityManager::snapshot`. Resources are not part of it.
+/// Components that don't override `Component::snapshot` are left out
+pub struct Snapshot {
+    world: EntityManager,
+}
+
+impl Snapshot {
+    pub(crate) fn new(world: EntityManager) -> Self {
+        Self { world }
+    }
+
+    /// Read only view of the copied world, every `EntityManager` getter works on it
+    pub fn world(&self) -> &EntityManager {
+        &self.world
+    }
+
+    /// Takes the copied world out of the snapshot, it has no resources
+    pub fn into_world(self) -> EntityManager {
+        self.world
+    }
+
+    /// Tick the snapshot was taken at
+    pub fn change_tick(&self) -> usize {
+        self.world.change_tick()
+    }
+
+    /// What changed going from this snapshot to `newer`. Components of spawned and despawned
+    /// entities are not listed, only those of entities alive in both.
+    /// Components are compared by their change ticks, not by value. Both snapshots have to come
+    /// from the same world, or from worlds that went through the same ticks like two deterministic runs
+    pub fn diff(&self, newer: &Snapshot) -> SnapshotDiff {
+        let old = self.world.entities().collect::<HashSet<_>>();
+        let new = newer.world.entities().collect::<HashSet<_>>();
+
+        let mut spawned = new.difference(&old).copied().collect::<Vec<_>>();
+        let mut despawned = old.difference(&new).copied().collect::<Vec<_>>();
+        spawned.sort();
+        despawned.sort();
+
+        let mut alive = old.intersection(&new).copied().collect::<Vec<_>>();
+        alive.sort();
+
+        let mut components = vec![];
+        for entity in alive {
+            let before = self
+                .world
+                .components()
+                .component_ticks(entity)
+                .into_iter()
+                .map(|(ty, name, ticks)| (ty, (name, ticks)))
+                .collect::<HashMap<_, _>>();
+            let mut after = newer.world.components().component_ticks(entity);
+            after.sort_by_key(|(_, name, _)| *name);
+
+            for (ty, name, ticks) in after.iter() {
+                let kind = match before.get(ty) {
+                    None => ComponentChangeKind::Added,
+                    Some((_, old_ticks)) if old_ticks != ticks => ComponentChangeKind::Changed,
+                    Some(_) => continue,
+                };
+
+                components.push(ComponentChange {
+                    entity,
+                    component: name,
+                    kind,
+                });
+            }
+
+            let mut removed = before
+                .iter()
+                .filter(|(ty, _)| after.iter().all(|(after_ty, _, _)| after_ty != *ty))
+                .map(|(_, (name, _))| *name)
+                .collect::<Vec<_>>();
+            removed.sort();
+
+            components.extend(removed.into_iter().map(|component| ComponentChange {
+                entity,
+                component,
+                kind: ComponentChangeKind::Removed,
+            }));
+        }
+
+        SnapshotDiff {
+            spawned,
+            despawned,
+            components,
+        }
+    }
+}
+
+impl Clone for Snapshot {
+    fn clone(&self) -> Self {
+        Snapshot::new(self.world.copy())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentChangeKind {
+    Added,
+    Removed,
+    /// Written to since the older snapshot, the value itself may be the same
+    Changed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentChange {
+    pub entity: EntityID,
+    /// Type name of the component
+    pub component: &'static str,
+    pub kind: ComponentChangeKind,
+}
+
+/// Structural difference between two snapshots, see `Snapshot::diff`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    pub spawned: Vec<EntityID>,
+    pub despawned: Vec<EntityID>,
+    pub components: Vec<ComponentChange>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.spawned.is_empty() && self.despawned.is_empty() && self.components.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::type_name;
+
+    use super::{ComponentChange, ComponentChangeKind};
+    use crate::core::{
+        components::{Component, StorageType},
+        engine::EntityManager,
+    };
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Speed(i32);
+    #[derive(Debug, Clone, PartialEq)]
+    struct Position(i32);
+    /// Left out of snapshots
+    #[derive(Debug)]
+    struct Window;
+
+    impl Component for Speed {
+        fn snapshot(&self) -> Option<Self> {
+            Some(self.clone())
+        }
+    }
+
+    impl Component for Position {
+        const STORAGE: StorageType = StorageType::Table;
+
+        fn snapshot(&self) -> Option<Self> {
+            Some(self.clone())
+        }
+    }
+
+    impl Component for Window {}
+
+    #[test]
+    fn restore_brings_back_ids_and_free_list() {
+        let mut entities = EntityManager::new();
+        let ids = (0..3)
+            .map(|i| {
+                let entity = entities.create_entity();
+                entities.insert_component(entity, Speed(i)).unwrap();
+                entity
+            })
+            .collect::<Vec<_>>();
+        assert!(entities.destroy_entity(ids[1]));
+        let snapshot = entities.snapshot();
+
+        let reused = entities.create_entity();
+        let appended = entities.create_entity();
+        assert!(entities.destroy_entity(ids[0]));
+        entities.get_component_mut::<Speed>(ids[2]).unwrap().0 = 20;
+
+        entities.restore(&snapshot);
+        assert_eq!(entities.entities().collect::<Vec<_>>(), [ids[0], ids[2]]);
+        assert_eq!(*entities.get_component::<Speed>(ids[0]).unwrap(), Speed(0));
+        assert_eq!(*entities.get_component::<Speed>(ids[2]).unwrap(), Speed(2));
+        assert!(!entities.is_alive(reused));
+
+        //The free list is back too, new entities get the same slots and generations again
+        assert_eq!(entities.create_entity(), reused);
+        assert_eq!(entities.create_entity(), appended);
+    }
+
+    #[test]
+    fn components_without_snapshot_are_dropped() {
+        let mut entities = EntityManager::new();
+        let entity = entities.create_entity();
+        entities.insert_component(entity, Speed(1)).unwrap();
+        entities.insert_component(entity, Window).unwrap();
+
+        let snapshot = entities.snapshot();
+        assert!(snapshot.world().has_component::<Speed>(entity));
+        assert!(!snapshot.world().has_component::<Window>(entity));
+
+        entities.restore(&snapshot);
+        assert!(!entities.has_component::<Window>(entity));
+        assert_eq!(*entities.get_component::<Speed>(entity).unwrap(), Speed(1));
+    }
+
+    #[test]
+    fn diff_lists_structural_and_component_changes() {
+        let mut entities = EntityManager::new();
+        let a = entities.spawn_bundle((Speed(1), Position(1))).unwrap();
+        let b = entities.spawn_bundle((Speed(2),)).unwrap();
+        let c = entities.spawn_bundle((Speed(3),)).unwrap();
+        let before = entities.snapshot();
+
+        let d = entities.spawn_bundle((Speed(4),)).unwrap();
+        assert!(entities.destroy_entity(b));
+        entities.get_component_mut::<Speed>(a).unwrap().0 += 1;
+        entities.remove_component::<Position>(a).unwrap();
+        entities.insert_component(c, Position(3)).unwrap();
+        //Not part of snapshots, so not part of the diff either
+        entities.insert_component(c, Window).unwrap();
+
+        let after = entities.snapshot();
+        let diff = before.diff(&after);
+        assert_eq!(diff.spawned, [d]);
+        assert_eq!(diff.despawned, [b]);
+        assert_eq!(
+            diff.components,
+            [
+                ComponentChange {
+                    entity: a,
+                    component: type_name::<Speed>(),
+                    kind: ComponentChangeKind::Changed,
+                },
+                ComponentChange {
+                    entity: a,
+                    component: type_name::<Position>(),
+                    kind: ComponentChangeKind::Removed,
+                },
+                ComponentChange {
+                    entity: c,
+                    component: type_name::<Position>(),
+                    kind: ComponentChangeKind::Added,
+                },
+            ]
+        );
+
+        assert!(after.diff(&entities.snapshot()).is_empty());
+    }
+}
@@ -1,17 +1,139 @@
+use std::{any::type_name, fmt};
+
 use crate::systems::{assets::AssetLoaderSystem, render::RenderSystem};
 
-use super::engine::{Engine, EntityManager, EntityManagerRef, SystemEvent};
+use super::{
+    commands::Commands,
+    engine::{Engine, EntityManager, EntityManagerRef, SystemEvent, WorldId},
+    query::Access,
+    schedule::{Schedule, Stage},
+};
 
 pub type SysResult<T> = Result<T, SystemError>;
 
 pub trait SystemTrait {
     fn init(&mut self) {}
+    /// Called once by `Engine::shutdown`, on the thread calling it, after the last frame
+    fn shutdown(&mut self) {}
+    /// Components the system queries. Used to report systems that will contend for the same locks.
+    /// A system that holds one component type while borrowing another can deadlock with an
+    /// unordered system doing the same in the opposite order. Declare both so
+    /// `Engine::access_conflicts` reports the pair, then order them with `before` or `after`
+    fn access(&self) -> Access {
+        Access::default()
+    }
     fn step(
         &mut self,
         time: usize,
         entities: &EntityManagerRef,
+        commands: &mut Commands,
         engine: &Engine,
     ) -> SysResult<Vec<SystemEvent>>;
+    /// Used in access conflict reports
+    fn name(&self) -> String {
+        type_name::<Self>().to_string()
+    }
+    /// Names other systems of the same stage refer to in `before` and `after`.
+    /// Several systems can share a label
+    fn labels(&self) -> Vec<&'static str> {
+        vec![]
+    }
+    /// Labels of the systems that have to wait for this one every frame
+    fn before(&self) -> Vec<&'static str> {
+        vec![]
+    }
+    /// Labels of the systems this one waits for every frame
+    fn after(&self) -> Vec<&'static str> {
+        vec![]
+    }
+    /// Systems that have to stay on the thread calling `Engine::update`, like the one owning the window.
+    /// Every other system runs on whichever pool thread is free
+    fn main_thread(&self) -> bool {
+        false
+    }
+    /// Fixed stage systems get the fixed timestep as `time`, variable stage ones
+    /// the frame delta. Both in milliseconds, see the `Time` resource for more
+    fn stage(&self) -> Stage {
+        Stage::Variable
+    }
+    /// Systems are boxed and called through a vtable. Built-in systems return their own
+    /// `System` variant instead so the worker loop dispatches them statically
+    fn into_system(self) -> System
+    where
+        Self: Sized + Send + 'static,
+    {
+        System::Dynamic(DynamicSystem(Box::new(self)))
+    }
+}
+
+/// Lets `Box<dyn SystemTrait + Send>` be passed to `EngineBuilder::add_system` as is
+impl SystemTrait for Box<dyn SystemTrait + Send> {
+    fn init(&mut self) {
+        self.as_mut().init()
+    }
+
+    fn shutdown(&mut self) {
+        self.as_mut().shutdown()
+    }
+
+    fn access(&self) -> Access {
+        self.as_ref().access()
+    }
+
+    fn step(
+        &mut self,
+        time: usize,
+        entities: &EntityManagerRef,
+        commands: &mut Commands,
+        engine: &Engine,
+    ) -> SysResult<Vec<SystemEvent>> {
+        self.as_mut().step(time, entities, commands, engine)
+    }
+
+    fn name(&self) -> String {
+        self.as_ref().name()
+    }
+
+    fn labels(&self) -> Vec<&'static str> {
+        self.as_ref().labels()
+    }
+
+    fn before(&self) -> Vec<&'static str> {
+        self.as_ref().before()
+    }
+
+    fn after(&self) -> Vec<&'static str> {
+        self.as_ref().after()
+    }
+
+    fn main_thread(&self) -> bool {
+        self.as_ref().main_thread()
+    }
+
+    fn stage(&self) -> Stage {
+        self.as_ref().stage()
+    }
+
+    fn into_system(self) -> System {
+        System::Dynamic(DynamicSystem(self))
+    }
+}
+
+/// Anything `EngineBuilder::add_system` accepts: a `System`, any `SystemTrait` or a boxed one
+pub trait IntoSystem {
+    fn into_system(self) -> System;
+}
+
+impl IntoSystem for System {
+    fn into_system(self) -> System {
+        self
+    }
+}
+
+impl<T: SystemTrait + Send + 'static> IntoSystem for T {
+    fn into_system(self) -> System {
+        SystemTrait::into_system(self)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -19,10 +141,15 @@ pub struct SampleSystem {
     pub(crate) name: String,
 }
 impl SystemTrait for SampleSystem {
+    fn into_system(self) -> System {
+        System::SampleSystem(self)
+    }
+
     fn step(
         &mut self,
         time: usize,
         entities: &EntityManagerRef,
+        _commands: &mut Commands,
         engine: &Engine,
     ) -> SysResult<Vec<SystemEvent>> {
         Ok(vec![])
@@ -42,14 +169,134 @@ pub enum System {
     SampleSystem(SampleSystem),
     RenderSystem(RenderSystem),
     AssetSystem(AssetLoaderSystem),
+    /// Systems defined outside the engine
+    Dynamic(DynamicSystem),
+}
+
+pub struct DynamicSystem(Box<dyn SystemTrait + Send>);
+
+impl fmt::Debug for DynamicSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DynamicSystem")
+            .field(&self.0.name())
+            .finish()
+    }
+}
+
+/// Two systems that may run at the same time touching the same component of the same world,
+/// at least one of them writing it.
+/// Both stay correct, the component lock just makes one wait for the other
+#[derive(Debug, Clone)]
+pub struct AccessConflict {
+    pub systems: (String, String),
+    pub components: Vec<&'static str>,
+    pub world: WorldId,
+}
+
+/// Checks every pair of systems of a stage the schedule doesn't order.
+/// Systems bound to different worlds never conflict, each world has its own locks
+pub fn find_conflicts(systems: &[(WorldId, System)], schedule: &Schedule) -> Vec<AccessConflict> {
+    let mut conflicts = vec![];
+
+    for (i, (world, system)) in systems.iter().enumerate() {
+        for (j, (other_world, other)) in systems.iter().enumerate().skip(i + 1) {
+            if world != other_world || schedule.is_ordered(i, j) {
+                continue;
+            }
+
+            let components = system.access().conflicts(&other.access());
+            if !components.is_empty() {
+                conflicts.push(AccessConflict {
+                    systems: (system.name(), other.name()),
+                    components,
+                    world: *world,
+                });
+            }
+        }
+    }
+
+    conflicts
 }
 
 impl System {
+    pub fn name(&self) -> String {
+        match self {
+            System::SampleSystem(sys) => format!("SampleSystem({})", sys.name),
+            System::RenderSystem(_) => "RenderSystem".to_string(),
+            System::AssetSystem(_) => "AssetSystem".to_string(),
+            System::Dynamic(DynamicSystem(sys)) => sys.name(),
+        }
+    }
+
+    pub fn access(&self) -> Access {
+        match self {
+            System::SampleSystem(sys) => sys.access(),
+            System::RenderSystem(sys) => sys.access(),
+            System::AssetSystem(sys) => sys.access(),
+            System::Dynamic(DynamicSystem(sys)) => sys.access(),
+        }
+    }
+
+    pub fn labels(&self) -> Vec<&'static str> {
+        match self {
+            System::SampleSystem(sys) => sys.labels(),
+            System::RenderSystem(sys) => sys.labels(),
+            System::AssetSystem(sys) => sys.labels(),
+            System::Dynamic(DynamicSystem(sys)) => sys.labels(),
+        }
+    }
+
+    pub fn before(&self) -> Vec<&'static str> {
+        match self {
+            System::SampleSystem(sys) => sys.before(),
+            System::RenderSystem(sys) => sys.before(),
+            System::AssetSystem(sys) => sys.before(),
+            System::Dynamic(DynamicSystem(sys)) => sys.before(),
+        }
+    }
+
+    pub fn after(&self) -> Vec<&'static str> {
+        match self {
+            System::SampleSystem(sys) => sys.after(),
+            System::RenderSystem(sys) => sys.after(),
+            System::AssetSystem(sys) => sys.after(),
+            System::Dynamic(DynamicSystem(sys)) => sys.after(),
+        }
+    }
+
+    pub fn main_thread(&self) -> bool {
+        match self {
+            System::SampleSystem(sys) => sys.main_thread(),
+            System::RenderSystem(sys) => sys.main_thread(),
+            System::AssetSystem(sys) => sys.main_thread(),
+            System::Dynamic(DynamicSystem(sys)) => sys.main_thread(),
+        }
+    }
+
+    pub fn stage(&self) -> Stage {
+        match self {
+            System::SampleSystem(sys) => sys.stage(),
+            System::RenderSystem(sys) => sys.stage(),
+            System::AssetSystem(sys) => sys.stage(),
+            System::Dynamic(DynamicSystem(sys)) => sys.stage(),
+        }
+    }
+
     pub fn init(&mut self) {
         match self {
             System::SampleSystem(sys) => sys.init(),
             System::RenderSystem(sys) => sys.init(),
             System::AssetSystem(sys) => sys.init(),
+            System::Dynamic(DynamicSystem(sys)) => sys.init(),
+        }
+    }
+
+    pub fn shutdown(&mut self) {
+        match self {
+            System::SampleSystem(sys) => sys.shutdown(),
+            System::RenderSystem(sys) => sys.shutdown(),
+            System::AssetSystem(sys) => sys.shutdown(),
+            System::Dynamic(DynamicSystem(sys)) => sys.shutdown(),
         }
     }
 
@@ -57,12 +304,14 @@ impl System {
         &mut self,
         time: usize,
         entities: &EntityManagerRef,
+        commands: &mut Commands,
         engine: &Engine,
     ) -> Result<Vec<SystemEvent>, SystemError> {
         match self {
-            System::SampleSystem(sys) => sys.step(time, entities, engine),
-            System::RenderSystem(sys) => sys.step(time, entities, engine),
-            System::AssetSystem(sys) => sys.step(time, entities, engine),
+            System::SampleSystem(sys) => sys.step(time, entities, commands, engine),
+            System::RenderSystem(sys) => sys.step(time, entities, commands, engine),
+            System::AssetSystem(sys) => sys.step(time, entities, commands, engine),
+            System::Dynamic(DynamicSystem(sys)) => sys.step(time, entities, commands, engine),
         }
     }
 }
@@ -0,0 +1,373 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    marker::PhantomData,
+    panic::{catch_unwind, resume_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+thread_local! {
+    /// Deque of the pool thread, with the pool it belongs to
+    static LOCAL: RefCell<Option<(*const Shared, Worker<Job>)>> = const { RefCell::new(None) };
+}
+
+struct Shared {
+    injector: Injector<Job>,
+    stealers: Vec<Stealer<Job>>,
+    running: AtomicBool,
+    sleep: Mutex<()>,
+    wake: Condvar,
+}
+
+impl Shared {
+    fn has_jobs(&self) -> bool {
+        !self.injector.is_empty() || self.stealers.iter().any(|stealer| !stealer.is_empty())
+    }
+}
+
+/// Work-stealing thread pool running the systems every frame and the jobs they spawn.
+/// Every thread has its own deque, idle threads steal from the others
+pub struct TaskPool {
+    shared: Arc<Shared>,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+    thread_count: usize,
+}
+
+impl TaskPool {
+    pub fn new(thread_count: usize) -> Self {
+        let workers = (0..thread_count)
+            .map(|_| Worker::new_lifo())
+            .collect::<Vec<_>>();
+
+        let shared = Arc::new(Shared {
+            injector: Injector::new(),
+            stealers: workers.iter().map(Worker::stealer).collect(),
+            running: AtomicBool::new(true),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+        });
+
+        let threads = workers
+            .into_iter()
+            .enumerate()
+            .map(|(i, worker)| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("daima-worker-{}", i))
+                    .spawn(move || work(shared, worker))
+                    .unwrap()
+            })
+            .collect();
+
+        Self {
+            shared,
+            threads: Mutex::new(threads),
+            thread_count,
+        }
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.thread_count
+    }
+
+    /// Stops the threads once they are done with their current job and joins them.
+    /// Returns how many were still busy after `timeout`, those are left detached.
+    /// Scopes keep working afterwards, their jobs run on the thread waiting on them
+    pub fn shutdown(&self, timeout: Duration) -> usize {
+        self.stop();
+
+        let deadline = Instant::now() + timeout;
+        let mut threads = self.threads.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            let (finished, busy): (Vec<_>, Vec<_>) =
+                threads.drain(..).partition(|thread| thread.is_finished());
+            for thread in finished {
+                let _ = thread.join();
+            }
+            *threads = busy;
+
+            if threads.is_empty() || Instant::now() >= deadline {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        //Dropping the handles detaches the threads
+        threads.drain(..).count()
+    }
+
+    /// Runs `f` and blocks until every job spawned on the scope, directly or not, is done.
+    /// The calling thread only runs jobs of this scope while it waits, so it can hold locks
+    /// the jobs of other scopes may be waiting for.
+    /// Panics of the jobs are carried over to the caller
+    pub fn scope<'s, R>(&'s self, f: impl FnOnce(&Scope<'s>) -> R) -> R {
+        let scope = Scope {
+            pool: self,
+            jobs: Arc::new(Injector::new()),
+            pending: AtomicUsize::new(0),
+            panic: Mutex::new(None),
+            _marker: PhantomData,
+        };
+
+        let result = catch_unwind(AssertUnwindSafe(|| f(&scope)));
+
+        while scope.pending.load(Ordering::Acquire) > 0 {
+            match steal(&scope.jobs) {
+                Some(job) => job(),
+                None => thread::yield_now(),
+            }
+        }
+
+        let panic = scope
+            .panic
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+
+        match (result, panic) {
+            (Err(panic), _) | (Ok(_), Some(panic)) => resume_unwind(panic),
+            (Ok(result), None) => result,
+        }
+    }
+
+    /// Runs one queued job of any scope on the calling thread. Returns false if there was none
+    pub fn run_pending(&self) -> bool {
+        match self.find_job() {
+            Some(job) => {
+                job();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn find_job(&self) -> Option<Job> {
+        LOCAL.with(|local| match &*local.borrow() {
+            Some((pool, worker)) if *pool == Arc::as_ptr(&self.shared) => {
+                find_job(&self.shared, Some(worker))
+            }
+            _ => find_job(&self.shared, None),
+        })
+    }
+
+    /// Jobs pushed from a thread of the pool go on its own deque
+    fn push(&self, job: Job) {
+        LOCAL.with(|local| match &*local.borrow() {
+            Some((pool, worker)) if *pool == Arc::as_ptr(&self.shared) => worker.push(job),
+            _ => self.shared.injector.push(job),
+        });
+
+        let _guard = self
+            .shared
+            .sleep
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.shared.wake.notify_one();
+    }
+
+    fn stop(&self) {
+        self.shared.running.store(false, Ordering::Release);
+
+        let _guard = self
+            .shared
+            .sleep
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.shared.wake.notify_all();
+    }
+}
+
+impl Drop for TaskPool {
+    fn drop(&mut self) {
+        self.stop();
+
+        let threads = self
+            .threads
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        for thread in threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn work(shared: Arc<Shared>, worker: Worker<Job>) {
+    LOCAL.with(|local| *local.borrow_mut() = Some((Arc::as_ptr(&shared), worker)));
+
+    while shared.running.load(Ordering::Acquire) {
+        let job = LOCAL.with(|local| {
+            let local = local.borrow();
+            find_job(&shared, local.as_ref().map(|(_, worker)| worker))
+        });
+
+        match job {
+            Some(job) => job(),
+            None => {
+                let guard = shared.sleep.lock().unwrap_or_else(PoisonError::into_inner);
+                //Pushes notify under the lock, the timeout only covers jobs a busy thread
+                //pushed on its own deque while this one was checking the others
+                if shared.running.load(Ordering::Acquire) && !shared.has_jobs() {
+                    let _ = shared
+                        .wake
+                        .wait_timeout(guard, Duration::from_millis(1))
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            }
+        }
+    }
+
+    LOCAL.with(|local| *local.borrow_mut() = None);
+}
+
+fn find_job(shared: &Shared, local: Option<&Worker<Job>>) -> Option<Job> {
+    if let Some(job) = local.and_then(Worker::pop) {
+        return Some(job);
+    }
+
+    loop {
+        let stolen = match local {
+            Some(local) => shared.injector.steal_batch_and_pop(local),
+            None => shared.injector.steal(),
+        }
+        .or_else(|| shared.stealers.iter().map(Stealer::steal).collect());
+
+        match stolen {
+            Steal::Success(job) => return Some(job),
+            Steal::Empty => return None,
+            Steal::Retry => continue,
+        }
+    }
+}
+
+fn steal(jobs: &Injector<Job>) -> Option<Job> {
+    loop {
+        match jobs.steal() {
+            Steal::Success(job) => return Some(job),
+            Steal::Empty => return None,
+            Steal::Retry => continue,
+        }
+    }
+}
+
+/// Jobs spawned on a scope may borrow anything that outlives the `TaskPool::scope` call
+pub struct Scope<'s> {
+    pool: &'s TaskPool,
+    /// The pool only queues tickets pointing here, so waiting on the scope never runs
+    /// the jobs of another one
+    jobs: Arc<Injector<Job>>,
+    pending: AtomicUsize,
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+    _marker: PhantomData<fn(&'s ()) -> &'s ()>,
+}
+
+impl<'s> Scope<'s> {
+    pub fn spawn(&self, job: impl FnOnce(&Scope<'s>) + Send + 's) {
+        self.pending.fetch_add(1, Ordering::AcqRel);
+
+        let scope = ScopePtr(self);
+        let job: Box<dyn FnOnce() + Send + 's> = Box::new(move || {
+            let scope = scope.get();
+            if let Err(panic) = catch_unwind(AssertUnwindSafe(|| job(scope))) {
+                scope
+                    .panic
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .get_or_insert(panic);
+            }
+            //Last use of the scope, `TaskPool::scope` may return right after
+            scope.pending.fetch_sub(1, Ordering::AcqRel);
+        });
+
+        //Safety: `TaskPool::scope` doesn't return before every job of the scope ran,
+        //so nothing the job borrows goes away first
+        let job: Job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 's>, Job>(job) };
+        self.jobs.push(job);
+
+        let jobs = self.jobs.clone();
+        self.pool.push(Box::new(move || {
+            if let Some(job) = steal(&jobs) {
+                job()
+            }
+        }));
+    }
+}
+
+struct ScopePtr<'s>(*const Scope<'s>);
+
+//The scope is Sync and outlives its jobs
+unsafe impl Send for ScopePtr<'_> {}
+
+impl<'s> ScopePtr<'s> {
+    fn get(&self) -> &Scope<'s> {
+        unsafe { &*self.0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        time::Duration,
+    };
+
+    use super::TaskPool;
+
+    #[test]
+    fn scope_waits_for_nested_jobs() {
+        let pool = TaskPool::new(4);
+        let done = AtomicUsize::new(0);
+
+        pool.scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|scope| {
+                    for _ in 0..8 {
+                        scope.spawn(|scope| {
+                            scope.spawn(|_| {
+                                done.fetch_add(1, Ordering::Relaxed);
+                            });
+                            done.fetch_add(1, Ordering::Relaxed);
+                        });
+                    }
+                    done.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+        });
+
+        assert_eq!(done.load(Ordering::Relaxed), 8 + 64 + 64);
+        assert_eq!(pool.shutdown(Duration::from_secs(5)), 0);
+    }
+
+    #[test]
+    fn pool_without_threads_runs_jobs_while_waiting() {
+        let pool = TaskPool::new(0);
+        let order = Mutex::new(vec![]);
+
+        pool.scope(|scope| {
+            for i in 0..4 {
+                let order = &order;
+                scope.spawn(move |scope| {
+                    order.lock().unwrap().push(i);
+                    scope.spawn(move |_| order.lock().unwrap().push(10 + i));
+                });
+            }
+            //Nothing runs before the scope starts waiting
+            assert!(order.lock().unwrap().is_empty());
+        });
+
+        //The waiting thread takes the jobs in the order they were spawned
+        assert_eq!(*order.lock().unwrap(), [0, 1, 2, 3, 10, 11, 12, 13]);
+        assert_eq!(pool.thread_count(), 0);
+    }
+}
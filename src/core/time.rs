@@ -0,0 +1,262 @@
+use std::time::{Duration, Instant};
+
+use super::schedule::Stage;
+
+/// Longest frame the fixed stage catches up on. Past it the simulation slows down
+/// instead of running more and more steps every frame
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// Frame timing. Every world has it as a resource, updated before each stage runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Time {
+    /// Wall clock time between the start of the previous frame and this one
+    pub delta: Duration,
+    /// Simulated time of one fixed step
+    pub fixed_delta: Duration,
+    /// How far the variable stage is between the last fixed step and the next one, from 0 to 1.
+    /// Render systems interpolate the state of the last two fixed steps with it
+    pub alpha: f32,
+    /// Simulated time, advanced by `fixed_delta` every fixed step
+    pub elapsed: Duration,
+    /// Stage being run
+    pub stage: Stage,
+}
+
+impl Time {
+    /// `fixed_delta` in the fixed stage, `delta` in the variable one
+    pub fn stage_delta(&self) -> Duration {
+        match self.stage {
+            Stage::Fixed => self.fixed_delta,
+            Stage::Variable => self.delta,
+        }
+    }
+}
+
+/// Measures the frames and accumulates their time into fixed steps
+#[derive(Debug, Clone)]
+pub(crate) struct Clock {
+    time: Time,
+    last_frame: Option<Instant>,
+    accumulator: Duration,
+    /// Every frame lasts exactly one fixed step, whatever the wall clock says
+    simulated: bool,
+}
+
+impl Clock {
+    pub(crate) fn new(fixed_delta: Duration) -> Self {
+        Self {
+            time: Time {
+                delta: Duration::ZERO,
+                fixed_delta,
+                alpha: 0.0,
+                elapsed: Duration::ZERO,
+                stage: Stage::Variable,
+            },
+            last_frame: None,
+            accumulator: Duration::ZERO,
+            simulated: false,
+        }
+    }
+
+    /// Clock of the deterministic mode, every frame runs one fixed step
+    pub(crate) fn simulated(fixed_delta: Duration) -> Self {
+        Self {
+            simulated: true,
+            ..Self::new(fixed_delta)
+        }
+    }
+
+    pub(crate) fn is_simulated(&self) -> bool {
+        self.simulated
+    }
+
+    pub(crate) fn time(&self) -> Time {
+        self.time
+    }
+
+    /// Measures the time since the previous frame started. The first frame has no delta
+    pub(crate) fn start_frame(&mut self) {
+        let delta = match self.simulated {
+            true => self.time.fixed_delta,
+            false => {
+                let now = Instant::now();
+                let delta = self
+                    .last_frame
+                    .map(|last_frame| now - last_frame)
+                    .unwrap_or_default();
+                self.last_frame = Some(now);
+                delta
+            }
+        };
+
+        self.advance(delta);
+    }
+
+    /// Starts a frame that lasted `delta`, capped at `MAX_FRAME_TIME` for the fixed steps
+    fn advance(&mut self, delta: Duration) {
+        self.time.delta = delta;
+        self.accumulator += delta.min(MAX_FRAME_TIME);
+    }
+
+    /// Takes one fixed step out of the accumulated time. False once less than a step is left
+    pub(crate) fn fixed_step(&mut self) -> bool {
+        if self.accumulator < self.time.fixed_delta {
+            return false;
+        }
+
+        self.accumulator -= self.time.fixed_delta;
+        self.time.elapsed += self.time.fixed_delta;
+        self.time.stage = Stage::Fixed;
+        true
+    }
+
+    /// Moves on to the variable stage once the fixed steps of the frame are done
+    pub(crate) fn variable_step(&mut self) {
+        self.time.alpha = self.accumulator.as_secs_f32() / self.time.fixed_delta.as_secs_f32();
+        self.time.stage = Stage::Variable;
+    }
+}
+
+/// How `Engine::run` paces the frames. Every world has the one in use as a resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FramePacing {
+    /// The render system waits for the display on every buffer swap.
+    /// Without a render system the engine falls back to `FixedSleep`
+    Vsync,
+    /// Sleeps whatever is left of the frame budget of the target frame rate
+    #[default]
+    FixedSleep,
+    /// Starts the next frame right away
+    Uncapped,
+}
+
+/// Frame time statistics, a resource of every world updated at the end of each frame
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FrameStats {
+    /// Frames completed
+    pub frames: usize,
+    /// Time between the start of the last frame and the one before it, pacing included.
+    /// The first frame has none. The fixed timestep in deterministic mode
+    pub frame_time: Duration,
+    /// Time the last frame spent in `Engine::update`, without the pacing.
+    /// Always zero in deterministic mode, see `EngineBuilder::deterministic`
+    pub update_time: Duration,
+    /// Moving average of `frame_time`
+    pub average_frame_time: Duration,
+    pub min_frame_time: Duration,
+    pub max_frame_time: Duration,
+    /// Events the systems sent during the last frame, every one of them was handled in it
+    pub events: usize,
+}
+
+impl FrameStats {
+    /// Frames per second from the average frame time
+    pub fn fps(&self) -> f64 {
+        match self.average_frame_time.is_zero() {
+            true => 0.0,
+            false => 1.0 / self.average_frame_time.as_secs_f64(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, frame_time: Duration, update_time: Duration, events: usize) {
+        self.frames += 1;
+        self.frame_time = frame_time;
+        self.update_time = update_time;
+        self.events = events;
+
+        match self.frames {
+            1 => (),
+            2 => {
+                self.average_frame_time = frame_time;
+                self.min_frame_time = frame_time;
+                self.max_frame_time = frame_time;
+            }
+            _ => {
+                self.average_frame_time =
+                    self.average_frame_time.mul_f64(0.9) + frame_time.mul_f64(0.1);
+                self.min_frame_time = self.min_frame_time.min(frame_time);
+                self.max_frame_time = self.max_frame_time.max(frame_time);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Clock, FrameStats, MAX_FRAME_TIME};
+    use crate::core::schedule::Stage;
+
+    const STEP: Duration = Duration::from_millis(16);
+
+    /// Runs the fixed steps of a frame that lasted `delta` and returns how many there were
+    fn frame(clock: &mut Clock, delta: Duration) -> u32 {
+        clock.advance(delta);
+        let mut steps = 0;
+        while clock.fixed_step() {
+            assert_eq!(clock.time().stage, Stage::Fixed);
+            steps += 1;
+        }
+        clock.variable_step();
+        steps
+    }
+
+    #[test]
+    fn accumulated_time_becomes_fixed_steps() {
+        let mut clock = Clock::new(STEP);
+
+        assert_eq!(frame(&mut clock, Duration::from_millis(10)), 0);
+        //The 10ms left over carry into the next frame
+        assert_eq!(frame(&mut clock, Duration::from_millis(40)), 3);
+        assert_eq!(clock.time().elapsed, STEP * 3);
+        assert_eq!(clock.time().alpha, 2.0 / 16.0);
+        assert_eq!(clock.time().stage, Stage::Variable);
+    }
+
+    #[test]
+    fn long_frames_are_capped() {
+        let mut clock = Clock::new(STEP);
+
+        let steps = frame(&mut clock, Duration::from_secs(10));
+        assert_eq!(
+            steps,
+            (MAX_FRAME_TIME.as_millis() / STEP.as_millis()) as u32
+        );
+        assert_eq!(clock.time().delta, Duration::from_secs(10));
+        //Nothing of the long frame is left to catch up on
+        assert_eq!(frame(&mut clock, Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn alpha_stays_below_one() {
+        let mut clock = Clock::new(STEP);
+
+        for millis in [0, 1, 15, 16, 17, 31, 32, 100, 1000] {
+            frame(&mut clock, Duration::from_millis(millis));
+            let alpha = clock.time().alpha;
+            assert!((0.0..1.0).contains(&alpha), "{} after {}ms", alpha, millis);
+        }
+    }
+
+    #[test]
+    fn frame_stats_average_frame_times() {
+        let mut stats = FrameStats::default();
+        assert_eq!(stats.fps(), 0.0);
+
+        //The first frame has no frame time to average
+        stats.record(Duration::ZERO, Duration::from_millis(1), 0);
+        assert_eq!(stats.fps(), 0.0);
+
+        stats.record(Duration::from_millis(20), Duration::from_millis(1), 2);
+        assert_eq!(stats.average_frame_time, Duration::from_millis(20));
+        assert_eq!(stats.fps(), 50.0);
+
+        stats.record(Duration::from_millis(10), Duration::from_millis(1), 0);
+        assert_eq!(stats.frames, 3);
+        assert_eq!(stats.average_frame_time, Duration::from_millis(19));
+        assert_eq!(stats.min_frame_time, Duration::from_millis(10));
+        assert_eq!(stats.max_frame_time, Duration::from_millis(20));
+        assert_eq!(stats.events, 0);
+    }
+}
@@ -1,4 +1,3 @@
-extern crate bus;
 extern crate crossbeam_channel;
 extern crate gl;
 extern crate glfw;
@@ -8,24 +7,27 @@ mod core;
 mod renderer;
 mod systems;
 
+use std::process::ExitCode;
+use std::time::Duration;
+
 use systems::assets::AssetLoaderSystem;
 use systems::render::RenderSystem;
 
 use crate::core::engine::EngineBuilder;
 use crate::core::level_manager::StarterLevel;
-use crate::core::system::System;
-use systems::render::RenderSystem;
+use crate::core::system::SampleSystem;
 
-fn main() {
+fn main() -> ExitCode {
     let mut engine = EngineBuilder::builder()
-        .add_system(System::SampleSystem(core::system::SampleSystem {
+        .add_system(SampleSystem {
             name: "0".to_string(),
-        }))
-        .add_system(System::RenderSystem(RenderSystem::new()))
-        .add_system(System::AssetSystem(AssetLoaderSystem::new()))
+        })
+        .add_system(RenderSystem::new())
+        .add_system(AssetLoaderSystem::new())
+        .set_level_manager(Box::new(StarterLevel {}))
         .build();
 
-    loop {
-        engine.update()
-    }
+    engine.run();
+
+    engine.shutdown(Duration::from_secs(1)).into()
 }
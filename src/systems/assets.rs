@@ -1,24 +1,86 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
 use crate::core::{
+    commands::Commands,
+    components::Component,
     engine::{Engine, EntityManagerRef},
-    system::{SysResult, SystemTrait},
+    prefab::{FromPrefabArgs, PrefabLoader, Prefabs},
+    query::Access,
+    system::{SysResult, System, SystemTrait},
 };
 
 #[derive(Debug)]
-pub struct AssetLoaderSystem {}
+pub struct AssetLoaderSystem {
+    prefab_loader: PrefabLoader,
+    pending_prefabs: Vec<PathBuf>,
+}
 
 impl SystemTrait for AssetLoaderSystem {
+    fn into_system(self) -> System {
+        System::AssetSystem(self)
+    }
+
+    fn access(&self) -> Access {
+        Access::default().read_resource::<Prefabs>()
+    }
+
     fn step(
         &mut self,
         time: usize,
         entities: &EntityManagerRef,
+        commands: &mut Commands,
         engine: &Engine,
     ) -> SysResult<Vec<crate::core::engine::SystemEvent>> {
+        if !self.pending_prefabs.is_empty() {
+            self.load_prefabs(entities, commands);
+        }
+
         Ok(vec![])
     }
 }
 
 impl AssetLoaderSystem {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            prefab_loader: PrefabLoader::new(),
+            pending_prefabs: vec![],
+        }
+    }
+
+    /// Queues a prefab file. Files are loaded in order on the first step
+    /// and registered with the `EntityManager` at the end of that frame
+    pub fn load_prefab(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pending_prefabs.push(path.into());
+        self
+    }
+
+    /// Lets prefab files use `T`, see `PrefabLoader::register`
+    pub fn register_prefab_component<T: Component + Clone + FromPrefabArgs>(
+        mut self,
+        name: &str,
+    ) -> Self {
+        self.prefab_loader.register::<T>(name);
+        self
+    }
+
+    fn load_prefabs(&mut self, entities: &EntityManagerRef, commands: &mut Commands) {
+        let entities = entities.read().unwrap();
+        let mut loaded = HashMap::new();
+
+        //Children may refer to prefabs loaded earlier in the same batch
+        for path in self.pending_prefabs.drain(..) {
+            let prefab = self.prefab_loader.load(&path, |name| {
+                loaded.get(name).cloned().or_else(|| entities.prefab(name))
+            });
+
+            match prefab {
+                Ok(prefab) => {
+                    let prefab = Arc::new(prefab);
+                    loaded.insert(prefab.name().to_string(), prefab.clone());
+                    commands.add_prefab(prefab);
+                }
+                Err(error) => eprintln!("Failed to load prefab {:?}: {:?}", path, error),
+            }
+        }
     }
 }
@@ -3,13 +3,15 @@ use std::fmt::{self, Debug};
 use std::ptr::null;
 use std::sync::Arc;
 
-use glfw::Context;
+use glfw::{Context, SwapInterval};
 
-use crate::core::engine::Engine;
+use crate::core::{camera::Camera, engine::Engine, input::Input, time::FramePacing};
 use crate::{
     core::{
+        commands::Commands,
         engine::{EntityManagerRef, SystemEvent},
-        system::{SysResult, SystemTrait},
+        query::Access,
+        system::{SysResult, System, SystemTrait},
     },
     renderer::window::{init_window, GlfwWindowContext},
 };
@@ -18,6 +20,8 @@ pub struct RenderSystem {
     window_context: Option<GlfwWindowContext>,
     shader_program: Option<u32>,
     vao: Option<u32>,
+    /// Swap interval set on the window, None until the first step
+    vsync: Option<bool>,
 }
 
 impl Debug for RenderSystem {
@@ -27,6 +31,22 @@ impl Debug for RenderSystem {
 }
 
 impl SystemTrait for RenderSystem {
+    fn into_system(self) -> System {
+        System::RenderSystem(self)
+    }
+
+    //The window and its GL context belong to the thread that created them
+    fn main_thread(&self) -> bool {
+        true
+    }
+
+    fn access(&self) -> Access {
+        Access::default()
+            .read_resource::<Camera>()
+            .read_resource::<FramePacing>()
+            .write_resource::<Input>()
+    }
+
     fn init(&mut self) {
         //Setup the windows
 
@@ -39,10 +59,28 @@ impl SystemTrait for RenderSystem {
         }
     }
 
+    fn shutdown(&mut self) {
+        if self.window_context.is_none() {
+            return;
+        }
+
+        unsafe {
+            if let Some(vao) = self.vao.take() {
+                gl::DeleteVertexArrays(1, &vao);
+            }
+            if let Some(shader_program) = self.shader_program.take() {
+                gl::DeleteProgram(shader_program);
+            }
+        }
+        //Closes the window
+        self.window_context = None;
+    }
+
     fn step(
         &mut self,
         time: usize,
         entities: &EntityManagerRef,
+        _commands: &mut Commands,
         engine: &Engine,
     ) -> SysResult<Vec<SystemEvent>> {
         let GlfwWindowContext {
@@ -51,6 +89,19 @@ impl SystemTrait for RenderSystem {
             events,
         } = self.window_context.as_mut().unwrap();
 
+        let vsync = entities
+            .read()
+            .unwrap()
+            .resource::<FramePacing>()
+            .is_some_and(|pacing| *pacing == FramePacing::Vsync);
+        if self.vsync != Some(vsync) {
+            glfw.set_swap_interval(match vsync {
+                true => SwapInterval::Sync(1),
+                false => SwapInterval::None,
+            });
+            self.vsync = Some(vsync);
+        }
+
         window.swap_buffers();
         glfw.poll_events();
 
@@ -58,17 +109,14 @@ impl SystemTrait for RenderSystem {
             return Ok(vec![SystemEvent::ShutdownEngine]);
         }
 
-        let window_events = glfw::flush_messages(events)
-            .into_iter()
-            .map(|(_i, e)| {
-                SystemEvent::EngineEvent(crate::core::engine::GameStateEvent::InputEvent(e))
-            })
-            .collect::<Vec<SystemEvent>>();
+        if let Some(mut input) = entities.read().unwrap().resource_mut::<Input>() {
+            input.update(glfw::flush_messages(events).map(|(_i, e)| e));
+        }
 
         unsafe {
-            self.render_to_window(engine);
+            self.render_to_window(entities);
         }
-        Ok(window_events)
+        Ok(vec![])
     }
 }
 
@@ -80,6 +128,7 @@ impl RenderSystem {
             window_context: None,
             shader_program: None,
             vao: None,
+            vsync: None,
         }
     }
 
@@ -198,9 +247,9 @@ impl RenderSystem {
         self.shader_program = Some(shader_program);
     }
 
-    unsafe fn render_to_window(&mut self, engine: &Engine) {
-        let camera = engine.camera.clone();
-        let camera_lock = camera.read().unwrap();
+    unsafe fn render_to_window(&mut self, entities: &EntityManagerRef) {
+        let entities = entities.read().unwrap();
+        let camera_lock = entities.resource::<Camera>().unwrap();
 
         gl::ClearColor(0.0, 0.0, 0.0, 1.0);
         gl::Clear(gl::COLOR_BUFFER_BIT);